@@ -1,25 +1,29 @@
 use std::mem;
 use glam::{Vec2, vec2};
 use macroquad::camera::{Camera2D, set_camera, set_default_camera};
-use macroquad::color::{BEIGE, BLUE, BROWN, Color, DARKBROWN, DARKGREEN, GRAY, GREEN, MAROON, PINK, SKYBLUE};
+use macroquad::color::{BEIGE, BLUE, BROWN, Color, DARKBROWN, DARKGREEN, GRAY, GREEN, MAROON, ORANGE, PINK, SKYBLUE, VIOLET};
 use macroquad::input::mouse_position;
 use macroquad::math::Rect;
 use macroquad::prelude::{clear_background, draw_line, draw_poly_lines, draw_rectangle};
 use macroquad::shapes::draw_rectangle_lines;
-use crate::model::branch::{Branch, BranchId, GrowthDecision, MLBranch};
-use crate::model::map::Map;
-use crate::model::Resource;
-use crate::model::soil::{Soil};
-use crate::numeric::distance_to_segment;
+use root_tactics::model::branch::{BranchHandle, GrowthDecision, RootTree};
+use root_tactics::model::map::Map;
+use root_tactics::model::Resource;
+use root_tactics::model::soil::{IMPASSABLE_HARDNESS, SOFT_SOIL, Soil};
+use root_tactics::model::water::WaterSourceShape;
+use root_tactics::numeric::Rng;
 use crate::ui::MainLayout;
 
 pub const SOIL_LEVEL: f32 = 50.0;
 
+/// How close the mouse must be to a root to hover it.
+const HOVER_DISTANCE: f32 = 5.0;
+
 
 pub fn draw_scene(
     map: &Map,
-    hover: &mut Option<BranchId>,
-    selected: &Option<BranchId>,
+    hover: &mut Option<(u32, BranchHandle)>,
+    selected: &Option<(u32, BranchHandle)>,
     layout: &MainLayout
 ) {
     clear_background(SKYBLUE);
@@ -32,20 +36,33 @@ pub fn draw_scene(
     set_camera(&camera);
 
     draw_rectangle(0.0, 0.0, map.size.x as f32, map.size.y as f32, DARKBROWN);
+    draw_hardness(map);
+    draw_water_sources(map);
 
     let mouse_pos: Vec2 = mouse_position().into();
     let mouse_pos = camera.screen_to_world(mouse_pos);
 
+    *hover = map.roots.nearest(mouse_pos)
+        .filter(|segment| segment.distance(mouse_pos) < HOVER_DISTANCE)
+        .map(|segment| (segment.plant, segment.branch));
+
     let plant_colors = [BEIGE, PINK, MAROON];
 
+    draw_remains(map);
+
     for (i, plant) in map.plants.iter().enumerate() {
-        draw_branch(&plant.root, mouse_pos, hover, plant_colors[i]);
-        let decision = plant.root.growth_decision(&map.soil, 1.0, &plant.strategy);
-        draw_decision(plant.root.segments[0].start.x, decision);
+        if !plant.is_alive() {
+            continue;
+        }
+        draw_branch(&plant.root, RootTree::MAIN, hover, plant_colors[i % plant_colors.len()]);
+        // Only the shares are drawn, so the directions may come from any generator.
+        let decision = plant.root.growth_decision(
+            RootTree::MAIN, &map.soil, &map.roots, 1.0, &plant.strategy, &mut Rng::new(0));
+        draw_decision(plant.root.main().segments[0].start.x, decision);
 
         if let Some(selected) = selected {
-            if i as u32 == selected.plant {
-                let selected_branch = plant.root.get_branch(&selected.branch_path);
+            if i as u32 == selected.0 {
+                let selected_branch = plant.root.get(selected.1);
                 if let Some(selected_branch) = selected_branch {
                     let mut p1 = selected_branch.segments[0].start;
                     let mut p2 = selected_branch.segments.last().unwrap().end;
@@ -71,16 +88,13 @@ pub fn draw_scene(
     for x in (0..map.size.x).step_by(20) {
         for y in (0..map.size.y).step_by(10) {
             let pos = vec2(x as f32, y as f32);
-            let water = map.soil.get_resource(pos, Resource::Water);
-            let nitro = map.soil.get_resource(pos, Resource::Nitro);
-
-            if water > 0.0 {
-                let size = resource_draw_size(water);
-                draw_poly_lines(pos.x, pos.y, 3, size, 0.0, 1.0, BLUE);
-            }
-            if nitro > 0.0 {
-                let size = resource_draw_size(nitro);
-                draw_poly_lines(pos.x + 5.0, pos.y + 2.0, 4, size, 0.0, 1.0, GRAY);
+            for what in Resource::ALL {
+                let quantity = map.soil.sample_resource(pos, what);
+                if quantity > 0.0 {
+                    let (sides, offset, color) = resource_glyph(what);
+                    let at = pos + offset;
+                    draw_poly_lines(at.x, at.y, sides, resource_draw_size(quantity), 0.0, 1.0, color);
+                }
             }
         }
     }
@@ -88,34 +102,80 @@ pub fn draw_scene(
     set_default_camera();
 }
 
-fn draw_branch(branch: &MLBranch, mouse_pos: Vec2, hover: &mut Option<BranchId>, color: Color) {
-    let mut color = color;
+/// Compacted soil and rocks, the harder the lighter.
+fn draw_hardness(map: &Map) {
+    let cell = map.soil.cell_size();
+    for x in (0..map.size.x).step_by(cell) {
+        for y in (0..map.size.y).step_by(cell) {
+            let hardness = map.soil.get_hardness(vec2(x as f32, y as f32));
+            if hardness > SOFT_SOIL {
+                let color = Color::new(0.6, 0.6, 0.6, f32::min(1.0, hardness / IMPASSABLE_HARDNESS));
+                draw_rectangle(x as f32, y as f32, cell as f32, cell as f32, color);
+            }
+        }
+    }
+}
 
-    if hover.is_none() {
-        let d_mouse = distance_to_segment(
-            mouse_pos,
-            branch.segments[0].start,
-            branch.segments.last().unwrap().end);
+fn draw_water_sources(map: &Map) {
+    let color = Color::new(0.0, 0.47, 0.95, 0.25);
+    for source in map.soil.water_sources() {
+        match &source.shape {
+            WaterSourceShape::Aquifer { top, bottom } =>
+                draw_rectangle(0.0, *top, map.size.x as f32, bottom - top, color),
+            WaterSourceShape::Creek { points, width } =>
+                for line in points.windows(2) {
+                    draw_line(line[0].x, line[0].y, line[1].x, line[1].y, 2.0 * width, color);
+                },
+        }
+    }
+}
 
-        if d_mouse < 5.0 {
-            color = GREEN;
-            *hover = Some(branch.id.clone())
+fn draw_remains(map: &Map) {
+    for remains in map.remains.iter() {
+        let freshness = remains.mass / remains.initial_mass;
+        let color = Color::new(0.3, 0.25, 0.2, 0.8 * freshness);
+        for (start, end) in remains.segments.iter() {
+            draw_line(start.x, start.y, end.x, end.y, 2.0, color);
         }
     }
+}
+
+/// The hovered branch is drawn green, with all its subtree.
+fn draw_branch(tree: &RootTree, handle: BranchHandle, hover: &Option<(u32, BranchHandle)>, color: Color) {
+    let branch = &tree[handle];
+    let color = if *hover == Some((tree.plant, handle)) { GREEN } else { color };
+
+    // Withering branches fade to gray.
+    let own_color = if branch.is_withering() {
+        let health = branch.health.clamp(0.0, 1.0);
+        Color::from_vec(GRAY.to_vec().lerp(color.to_vec(), health))
+    } else {
+        color
+    };
 
     for (i, segment) in branch.segments.iter().enumerate() {
-        if let Some(left) = &segment.branch {
-            draw_branch(left, mouse_pos, hover, color);
+        if let Some(left) = segment.branch {
+            draw_branch(tree, left, hover, color);
         }
 
-        let thickness = 7.0 * (branch.get_length() - i as f32) / branch.get_length();
+        let thickness = 2.0 * branch.segment_radius(i);
         draw_line(
             segment.start.x,
             segment.start.y,
             segment.end.x,
             segment.end.y,
             1.0 + thickness,
-            color);
+            own_color);
+    }
+}
+
+/// * returns (polygon sides, offset within the cell, color)
+fn resource_glyph(what: Resource) -> (u8, Vec2, Color) {
+    match what {
+        Resource::Water => (3, vec2(0.0, 0.0), BLUE),
+        Resource::Nitro => (4, vec2(5.0, 2.0), GRAY),
+        Resource::Phosphorus => (5, vec2(10.0, 5.0), ORANGE),
+        Resource::Potassium => (6, vec2(15.0, 2.0), VIOLET),
     }
 }
 
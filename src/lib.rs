@@ -0,0 +1,6 @@
+//! The game model. Knows nothing about windows or rendering, so it runs headless
+//! in tests and batch jobs as well as under the macroquad frontend in `main.rs`.
+
+pub mod numeric;
+pub mod stats;
+pub mod model;
@@ -1,6 +1,3 @@
-mod numeric;
-mod stats;
-mod model;
 mod draw;
 mod ui;
 
@@ -9,14 +6,18 @@ use macroquad::color::LIGHTGRAY;
 use macroquad::input::{is_key_down, is_key_pressed, is_mouse_button_pressed, KeyCode, MouseButton};
 // use macroquad::texture::{load_texture, Texture2D};
 use macroquad::window::{clear_background, Conf, next_frame, screen_height, screen_width};
+use root_tactics::model::branch::{Branch, BranchHandle, RootTree};
+use root_tactics::model::plant::Plant;
+use root_tactics::model::Resource;
+use root_tactics::model::save;
+use root_tactics::model::scenario::{Scenario, ScenarioError};
+use root_tactics::model::simulation::Simulation;
 use crate::draw::{draw_scene, SOIL_LEVEL};
-use crate::model::branch::{Branch, MLBranch};
-use crate::model::map::Map;
-use crate::model::plant::Plant;
-use crate::model::soil::{MatrixSoil, Soil};
 use crate::ui::{draw_ui, IngameUi, MainLayout};
 
 
+const SAVE_FILE: &str = "savegame.json";
+
 fn window_conf() -> Conf {
     Conf {
         window_title: "Root Tactics".to_owned(),
@@ -31,36 +32,61 @@ struct State {
     // pub soil: MatrixSoil,
     // pub plants: Vec<Plant>,
     pub ui_state: IngameUi,
-    pub map: Map,
+    pub simulation: Simulation,
 
     pub ui_layout: MainLayout,
 }
 
 impl State {
-    pub fn new() -> Self {
-        let map_size = ivec2(screen_width() as i32 - 120, (screen_height() - SOIL_LEVEL) as i32);
+    pub fn new(simulation: Simulation) -> Self {
         Self {
-            map: Map::new(map_size, 300),
+            simulation,
             ui_state: IngameUi::new(),
             ui_layout: MainLayout { sidebar_width: 120.0, font_size: 12.0 }
         }
     }
 }
 
-fn print_branch(branch: &MLBranch, offset: usize) {
+/// Value of a `--name value` command line argument.
+fn arg_value(name: &str) -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+        .cloned()
+}
+
+/// `--seed <number>` replays a world, otherwise the seed comes from the clock.
+fn seed_from_args() -> u64 {
+    arg_value("--seed")
+        .and_then(|seed| seed.parse().ok())
+        // Unlike `SystemTime`, miniquad's clock works on wasm too.
+        .unwrap_or_else(|| macroquad::miniquad::date::now() as u64)
+}
+
+/// `--scenario <file.json>` sets up the map from a file, otherwise it's the built-in one.
+fn simulation_from_args(seed: u64) -> Result<Simulation, ScenarioError> {
+    match arg_value("--scenario") {
+        Some(path) => Ok(Simulation::from_scenario(seed, &Scenario::load(path)?)),
+        None => {
+            let map_size = ivec2(screen_width() as i32 - 120, (screen_height() - SOIL_LEVEL) as i32);
+            Ok(Simulation::new(seed, map_size, 300))
+        }
+    }
+}
+
+fn print_branch(tree: &RootTree, handle: BranchHandle, offset: usize) {
+    let branch = &tree[handle];
     println!(
         "{: <1$}Branch {2}, length {3}, weight {4}, has {5} children:",
-        "", offset, branch.id, branch.get_length(), branch.get_weight(), branch.branch_count());
-    for segment in branch.segments.iter() {
-        if let Some(branch) = segment.branch.as_ref() {
-            print_branch(branch, offset + 2);
-        }
+        "", offset, tree.id(handle), branch.get_length(), branch.get_weight(), branch.branch_count());
+    for child in branch.children() {
+        print_branch(tree, child, offset + 2);
     }
 }
 
 fn print_plant(p0: &Plant) {
-    let branch = &p0.root;
-    print_branch(branch, 0);
+    print_branch(&p0.root, RootTree::MAIN, 0);
 }
 
 // pub struct Textures {
@@ -84,7 +110,16 @@ async fn main() {
 
     clear_background(LIGHTGRAY);
 
-    let mut state = State::new();
+    let seed = seed_from_args();
+    println!("Seed: {}", seed);
+    let simulation = match simulation_from_args(seed) {
+        Ok(simulation) => simulation,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let mut state = State::new(simulation);
 
     loop {
         if is_key_pressed(KeyCode::Q) {
@@ -92,28 +127,47 @@ async fn main() {
         }
 
         if state.ui_state.speed == 1 || is_key_down(KeyCode::G) {
-            for plant in state.map.plants.iter_mut() {
-                plant.grow(&mut state.map.soil);
+            state.simulation.step();
+        }
+
+        if is_key_pressed(KeyCode::S) {
+            match save::save(&state.simulation, SAVE_FILE) {
+                Ok(()) => println!("Saved tick {} to {}", state.simulation.tick, SAVE_FILE),
+                Err(e) => println!("Save failed: {}", e),
+            }
+        }
+
+        if is_key_pressed(KeyCode::L) {
+            match save::load(SAVE_FILE) {
+                Ok(simulation) => {
+                    println!("Loaded tick {} from {}", simulation.tick, SAVE_FILE);
+                    state.simulation = simulation;
+                    // The selected branches may be gone in the loaded world.
+                    state.ui_state = IngameUi { speed: state.ui_state.speed, ..IngameUi::new() };
+                }
+                Err(e) => println!("Load failed: {}", e),
             }
         }
 
-        if is_key_pressed(KeyCode::P) {
-            print_plant(&state.map.plants[0]);
+        if let Some(plant) = state.simulation.map.plants.first().filter(|_| is_key_pressed(KeyCode::P)) {
+            print_plant(plant);
         }
 
         state.ui_state.hovered = None;
-        draw_scene(&state.map, &mut state.ui_state.hovered, &state.ui_state.selected, &state.ui_layout);
+        draw_scene(&state.simulation.map, &mut state.ui_state.hovered, &state.ui_state.selected, &state.ui_layout);
 
-        if is_mouse_button_pressed(MouseButton::Left) && state.ui_state.hovered.is_some() {
-            let selected = state.ui_state.hovered.clone().unwrap();
-            let plant = state.map.plants[selected.plant as usize].root.get_branch(&selected.branch_path);
+        if let Some(selected) = state.ui_state.hovered.filter(|_| is_mouse_button_pressed(MouseButton::Left)) {
+            let plant = state.simulation.map.plants[selected.0 as usize].root.get(selected.1);
             state.ui_state.selected_mass = plant.map(|branch| branch.get_weight());
             state.ui_state.selected_water_consumption = plant.map(|branch| branch.get_weight() * 0.21);
             state.ui_state.selected_nitro_consumption = plant.map(|branch| branch.get_weight() * 0.034);
+            let soil = &state.simulation.map.soil;
+            state.ui_state.selected_water_potential = plant.map(|branch| branch.get_suck_potential(Resource::Water, soil));
+            state.ui_state.selected_nitro_potential = plant.map(|branch| branch.get_suck_potential(Resource::Nitro, soil));
             state.ui_state.selected = Some(selected);
         }
 
-        draw_ui(&state.map, &mut state.ui_state, &state.ui_layout);
+        draw_ui(&state.simulation.map, &mut state.ui_state, &state.ui_layout);
 
         next_frame().await;
     }
@@ -1,14 +1,17 @@
 use std::f32::consts::PI;
 use std::fmt::{Display, Formatter};
-use std::ops::Deref;
+use std::ops::{Index, IndexMut};
 use glam::{Vec2, vec2};
 use num_traits::FloatConst;
+use serde::{Deserialize, Serialize};
 
-use crate::{MatrixSoil, Soil};
-use crate::model::{BranchingStrategy, Resource};
+use crate::model::{Amounts, BranchingStrategy, Crowding, Resource};
+use crate::model::soil::{availability, IMPASSABLE_HARDNESS, MatrixSoil, SOFT_SOIL, Soil, UptakeRequest};
+use crate::model::spatial::{IndexedSegment, SegmentIndex};
+use crate::numeric::Rng;
 
 
-/// All recursive.
+/// A single branch, not counting its children. See `RootTree` for the whole subtree.
 pub trait Branch {
     fn get_length(&self) -> f32;
     fn get_radius(&self) -> f32;
@@ -16,20 +19,157 @@ pub trait Branch {
     fn get_weight(&self) -> f32;
     // fn get_conductivity(&self) -> f32;
 
-    fn grow(&mut self, nutri: f32, soil: &MatrixSoil);
-    fn get_suck_potential(&self, what: Resource) -> f32;
+    /// How much of the resource the branch could take from the soil in a tick.
+    fn get_suck_potential(&self, what: Resource, soil: &MatrixSoil) -> f32;
+}
+
+/// How a tree of branches spends the material its plant made, so that other
+/// kinds of trees can grow their own way. See `RootTree` for the usual one.
+pub trait Grow {
+    /// Spends `new_material` on the subtree, the way the strategy says.
+    /// * `roots` - all the roots on the map, to keep clear of. New segments are added to it.
+    fn grow(
+        &mut self,
+        handle: BranchHandle,
+        // how much mass this branch or its children can gain.
+        new_material: f32,
+        soil: &MatrixSoil,
+        roots: &mut SegmentIndex,
+        strategy: &BranchingStrategy,
+        rng: &mut Rng,
+    );
 }
 
 
 /// Distance between points in multiline.
 const SEGMENT_LENGTH: f32 = 1.0;
 
+/// A branch is a truncated cone, this many times as thick at the base as at the tip.
+/// Real roots taper less over a single order, but a branch here stands for its
+/// laterals too, and the base has to leave room for all the children on it
+/// while the tip stays thin enough to push into the soil.
+/// See `BranchingStrategy::conic_ratio`.
+const TAPER: f32 = 2.0;
+
+/// How much of a resource a unit of root surface can pull per tick.
+const UPTAKE_RATE: f32 = 0.1;
+
+/// Cellulose a unit of weight consumes per tick just to stay alive.
+pub const UPKEEP_RATE: f32 = 0.01;
+
+/// Health lost per tick by a branch that got none of its upkeep.
+const WITHERING_RATE: f32 = 0.05;
+
+/// Health regained per tick by a fully supplied branch.
+const RECOVERY_RATE: f32 = 0.02;
+
+
+/// Pushing into hard soil wastes material: only `1 / hardness_cost` of it becomes the root.
+fn hardness_cost(soil: &MatrixSoil, pos: Vec2) -> f32 {
+    f32::max(SOFT_SOIL, soil.get_hardness(pos))
+}
+
+/// Turns a step from `from` to `to` away from obstacles, trying ever sharper angles,
+/// downwards first.
+/// * returns the new end of the step, or None if the tip is stuck.
+fn step_around(from: Vec2, to: Vec2, passable: impl Fn(Vec2) -> bool) -> Option<Vec2> {
+    let step = to - from;
+    if passable(to) {
+        return Some(to);
+    }
+
+    (1..=4)
+        .flat_map(|i| {
+            let angle = i as f32 * f32::PI() / 8.0;
+            let mut turns = [
+                from + Vec2::from_angle(angle).rotate(step),
+                from + Vec2::from_angle(-angle).rotate(step),
+            ];
+            turns.sort_by(|a, b| b.y.total_cmp(&a.y));
+            turns
+        })
+        .find(|point| passable(*point))
+}
+
+/// Roots keep this far from other roots, see `Crowding`.
+const ROOT_SPACING: f32 = 0.5;
+
+/// Whether a step from `from` to `to` gets too close to other roots.
+/// The segments the step starts from don't count.
+/// * `plant` - who steps. With `Crowding::Compete`, other plants' roots don't count either.
+fn is_crowded(roots: &SegmentIndex, from: Vec2, to: Vec2, plant: u32, crowding: Crowding) -> bool {
+    roots.within(to, ROOT_SPACING).iter()
+        .filter(|segment| segment.distance(from) > f32::EPSILON)
+        .any(|segment| crowding != Crowding::Compete || segment.plant == plant)
+}
+
+/// Where a tip of `plant` heading from `from` to `to` can actually grow,
+/// around rocks and, the way the strategy says, around other roots.
+/// * returns None if the tip is stuck.
+fn find_way(
+    soil: &MatrixSoil,
+    roots: &SegmentIndex,
+    plant: u32,
+    strategy: &BranchingStrategy,
+    from: Vec2,
+    to: Vec2,
+) -> Option<Vec2> {
+    if strategy.crowding == Crowding::Stop && is_crowded(roots, from, to, plant, strategy.crowding) {
+        return None;
+    }
+    step_around(from, to, |point| soil.get_hardness(point) < IMPASSABLE_HARDNESS
+        && !is_crowded(roots, from, point, plant, strategy.crowding))
+}
+
+/// How far ahead growing tips feel for water and nitro.
+const TROPISM_RANGE: f32 = 10.0;
+
+/// Half of the angle of the cone ahead of a tip, where it feels for resources.
+const TROPISM_CONE: f32 = PI / 4.0;
+
+/// Directions sampled within the cone, evenly from one side to the other.
+const TROPISM_RAYS: usize = 5;
+
+/// The pull of water and nitro ahead of a tip: the sampled directions,
+/// each weighted by how much better than average it is, by the strategy's sensitivities.
+fn tropism(soil: &MatrixSoil, tip: Vec2, direction: Vec2, strategy: &BranchingStrategy) -> Vec2 {
+    let rays: Vec<(Vec2, f32)> = (0..TROPISM_RAYS)
+        .map(|i| {
+            let angle = TROPISM_CONE * (2.0 * i as f32 / (TROPISM_RAYS - 1) as f32 - 1.0);
+            let ray = Vec2::from_angle(angle).rotate(direction);
+            let point = tip + ray * TROPISM_RANGE;
+            let attraction = strategy.hydrotropism * soil.sample_resource(point, Resource::Water)
+                + strategy.chemotropism * soil.sample_resource(point, Resource::Nitro);
+            (ray, attraction)
+        })
+        .collect();
+
+    let average = rays.iter().map(|(_, attraction)| attraction).sum::<f32>() / rays.len() as f32;
+    rays.iter()
+        .map(|(ray, attraction)| *ray * (attraction - average))
+        .fold(Vec2::ZERO, |sum, pull| sum + pull)
+}
+
+/// Turns the growth direction of a tip at `tip` towards water and nitro ahead,
+/// then downwards by the strategy's gravitropism,
+/// and then by a random angle of up to `direction_noise` either way.
+/// * returns a unit vector.
+fn steer(soil: &MatrixSoil, tip: Vec2, direction: Vec2, strategy: &BranchingStrategy, rng: &mut Rng) -> Vec2 {
+    let direction = direction.normalize_or_zero();
+    let direction = direction + tropism(soil, tip, direction, strategy);
+    let pulled = direction.normalize_or_zero().lerp(Vec2::Y, strategy.gravitropism.clamp(0.0, 1.0));
+    let pulled = if pulled.length_squared() > f32::EPSILON { pulled.normalize() } else { Vec2::Y };
+    let turn = rng.range(-strategy.direction_noise, strategy.direction_noise);
+    Vec2::from_angle(turn).rotate(pulled)
+}
 
+#[derive(Serialize, Deserialize)]
 pub struct Segment {
     // `start` duplicates the end of a previous one. Not optimal, but convenient.
     pub start: Vec2,
     pub end: Vec2,
-    pub branch: Option<Box<MLBranch>>,
+    /// The branch growing from my end, if any.
+    pub branch: Option<BranchHandle>,
 }
 
 impl Segment {
@@ -48,7 +188,9 @@ impl Segment {
     }
 }
 
-#[derive(Clone)]
+/// Where a branch is, for showing it: the plant, and the index of the parent's segment
+/// every branch on the way from the main root grows from. See `RootTree::id`.
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct BranchId {
     pub plant: u32,
     pub branch_path: Vec<usize>
@@ -61,15 +203,6 @@ impl BranchId {
             branch_path: vec![]
         }
     }
-
-    pub fn append(&self, segment: usize) -> Self {
-        let mut path = self.branch_path.clone();
-        path.push(segment);
-        Self {
-            plant: self.plant,
-            branch_path: path
-        }
-    }
 }
 
 impl Display for BranchId {
@@ -83,14 +216,24 @@ impl Display for BranchId {
 
 }
 
+/// A branch in its plant's `RootTree`. Stays valid while the branch lives,
+/// whatever grows or dies around it, and never points to another branch once it's gone.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct BranchHandle {
+    index: u32,
+    /// Bumped each time the slot is emptied, to tell the old handles from the new ones.
+    generation: u32,
+}
+
 /// ML stands for "multiline", a sequence of line segments.
+#[derive(Serialize, Deserialize)]
 pub struct MLBranch {
-    // A sequence of branch indexes, from the root of the root.
-    pub id: BranchId,
+    /// The branch I grow from. None for the main root.
+    pub parent: Option<BranchHandle>,
 
     pub segments: Vec<Segment>,
 
-    /// Index in the parent's segments, where `self` branched off. No sense for a root MLBranch.
+    /// Index in the parent's segments, where `self` branched off. No sense for the main root.
     pub parent_segment_index: usize,
 
     /// My own weight
@@ -99,30 +242,55 @@ pub struct MLBranch {
     subtree_weight: f32,
 
     /// Best of `self` subtree's resource concentration.
-    /// Maintain this invariant!
+    /// Maintain this invariant! See `RootTree::update_subtree` and `RootTree::validate`.
     pub best_nitro: f32,
     pub best_water: f32,
+    /// Best uptake of a single one of my own segments in the last tick.
+    #[serde(default)]
+    own_best_nitro: f32,
+    #[serde(default)]
+    own_best_water: f32,
+
+    /// All resources brought by my own segments in the last tick.
+    #[serde(default)]
+    pub intake: f32,
+
+    /// 1.0 is healthy. Goes down while the upkeep isn't paid, the branch dies at 0.0.
+    #[serde(default = "full_health")]
+    pub health: f32,
+    /// Ticks lived.
+    #[serde(default)]
+    pub age: u32,
 }
 
+fn full_health() -> f32 { 1.0 }
+
 impl Branch for MLBranch {
     fn get_length(&self) -> f32 { self.segments.len() as f32 }
 
     /// This would be the *average* radius.
     fn get_radius(&self) -> f32 {
-        // Taking it as a cylinder, so far, while it should be cone.
-        (self.weight / (self.get_length() * PI)).sqrt()
+        self.tip_radius() * (1.0 + TAPER) / 2.0
     }
 
-    fn get_surface(&self) -> f32 { self.weight / self.get_length() as f32 }
+    /// Side surface of the whole branch, not counting the children.
+    fn get_surface(&self) -> f32 {
+        (0..self.segments.len()).map(|i| self.segment_surface(i)).sum()
+    }
 
     fn get_weight(&self) -> f32 { self.weight }
 
-    fn grow(&mut self, _nutri: f32, _soil: &MatrixSoil) {
-        todo!()
-    }
-
-    fn get_suck_potential(&self, _what: Resource) -> f32 {
-        100.0
+    /// What my own segments could pull if nobody competed: each as much as its surface allows,
+    /// but no more than the soil around it makes available.
+    fn get_suck_potential(&self, what: Resource, soil: &MatrixSoil) -> f32 {
+        self.segments.iter()
+            .enumerate()
+            .map(|(i, segment)| {
+                let available = soil.sample_resource(segment.end, what)
+                    * availability(what, soil.get_ph(segment.end));
+                f32::min(UPTAKE_RATE * self.segment_surface(i), available)
+            })
+            .sum()
     }
 }
 
@@ -144,94 +312,401 @@ pub enum GrowthDecision {
 
 impl MLBranch {
 
-    pub fn new(plant: u32, x: f32, weight: f32) -> Self {
-        Self {
-            id: BranchId::new(plant),
-            segments: vec![
-                Segment::new(vec2(x, 0.0), vec2(x, SEGMENT_LENGTH))
-            ],
-            parent_segment_index: 0,
-            weight,
-            subtree_weight: weight,
-            best_nitro: 0.0,
-            best_water: 0.0
-        }
+    /// A main root, going down from `x` at the surface.
+    pub fn new(x: f32, weight: f32) -> Self {
+        Self::new_branch(vec2(x, 0.0), vec2(x, SEGMENT_LENGTH), None, 0, weight)
     }
 
-    pub fn new_branch(start: Vec2, end: Vec2, parent_segment_index: usize, parent_id: &BranchId, weight: f32) -> Self {
+    pub fn new_branch(
+        start: Vec2,
+        end: Vec2,
+        parent: Option<BranchHandle>,
+        parent_segment_index: usize,
+        weight: f32,
+    ) -> Self {
         Self {
-            id: parent_id.append(parent_segment_index),
+            parent,
             segments: vec![ Segment::new(start, end) ],
             parent_segment_index,
             weight,
             subtree_weight: weight,
             best_nitro: 0.0,
-            best_water: 0.0
+            best_water: 0.0,
+            own_best_nitro: 0.0,
+            own_best_water: 0.0,
+            intake: 0.0,
+            health: 1.0,
+            age: 0,
         }
     }
 
     pub fn branch_count(&self) -> usize {
-        self.segments.iter()
-            .filter_map(|s| s.branch.as_ref().map(|_| true))
-            .count()
+        self.children().count()
+    }
+
+    /// Handles of the branches growing from my segments, from the base to the tip.
+    pub fn children(&self) -> impl Iterator<Item = BranchHandle> + '_ {
+        self.segments.iter().filter_map(|segment| segment.branch)
+    }
+
+    fn last_branch_index(&self) -> Option<usize> {
+        self.segments.iter().enumerate()
+            .rev()
+            .find_map(|(i, s)| s.branch.map(|_| i))
+    }
+
+    pub fn upkeep(&self) -> f32 {
+        UPKEEP_RATE * self.weight
+    }
+
+    pub fn is_withering(&self) -> bool {
+        self.health < 1.0
     }
 
-    pub fn get_branch(&self, branch_path: &Vec<usize>) -> Option<&MLBranch> {
-        let mut branch = self;
-        for branch_index in branch_path {
-            match branch.segments[*branch_index].branch.as_ref() {
-                None => return None,
-                Some(branch_box) => branch = branch_box.deref(),
+    pub fn is_dead(&self) -> bool {
+        self.health <= 0.0
+    }
+
+    fn tip(&self) -> Vec2 {
+        self.segments.last().expect("Empty branch, really?").end
+    }
+
+    /// Radius at the thin end, for `weight` of material of unit density.
+    fn tip_radius(&self) -> f32 {
+        // The volume of a truncated cone is PI * L / 3 * (R^2 + R*r + r^2), where R = TAPER * r.
+        let shape = (TAPER * TAPER + TAPER + 1.0) / 3.0;
+        (self.weight / (PI * self.get_length() * shape)).sqrt()
+    }
+
+    /// Radius in the middle of a segment, shrinking from the base to the tip.
+    pub fn segment_radius(&self, index: usize) -> f32 {
+        let along = (index as f32 + 0.5) / self.segments.len() as f32;
+        self.tip_radius() * (TAPER - (TAPER - 1.0) * along)
+    }
+
+    /// Side surface of a single segment, taking it as a cylinder of its radius.
+    fn segment_surface(&self, index: usize) -> f32 {
+        2.0 * PI * self.segment_radius(index) * SEGMENT_LENGTH
+    }
+}
+
+/// A place for a branch in `RootTree`, reused once the branch is gone.
+#[derive(Serialize, Deserialize)]
+struct Slot {
+    generation: u32,
+    branch: Option<MLBranch>,
+}
+
+/// All the branches of a plant's root, kept in an arena. They refer to their parent
+/// and children by `BranchHandle`, so finding or adding one is O(1).
+/// All recursive methods work on the subtree of the given branch.
+#[derive(Serialize, Deserialize)]
+pub struct RootTree {
+    pub plant: u32,
+    slots: Vec<Slot>,
+    /// Indexes of the empty slots.
+    free: Vec<u32>,
+}
+
+impl Index<BranchHandle> for RootTree {
+    type Output = MLBranch;
+
+    fn index(&self, handle: BranchHandle) -> &MLBranch {
+        self.get(handle).expect("The branch is gone")
+    }
+}
+
+impl IndexMut<BranchHandle> for RootTree {
+    fn index_mut(&mut self, handle: BranchHandle) -> &mut MLBranch {
+        self.get_mut(handle).expect("The branch is gone")
+    }
+}
+
+impl RootTree {
+    /// The main root, which everything else grows from. It stays in the tree even when dead.
+    pub const MAIN: BranchHandle = BranchHandle { index: 0, generation: 0 };
+
+    /// A tree of just the main root, going down from `x` at the surface.
+    pub fn new(plant: u32, x: f32, weight: f32) -> Self {
+        Self {
+            plant,
+            slots: vec![ Slot { generation: 0, branch: Some(MLBranch::new(x, weight)) } ],
+            free: vec![],
+        }
+    }
+
+    pub fn main(&self) -> &MLBranch {
+        &self[Self::MAIN]
+    }
+
+    /// * returns None if the branch is gone.
+    pub fn get(&self, handle: BranchHandle) -> Option<&MLBranch> {
+        self.slots.get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.branch.as_ref())
+    }
+
+    pub fn get_mut(&mut self, handle: BranchHandle) -> Option<&mut MLBranch> {
+        self.slots.get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.branch.as_mut())
+    }
+
+    /// The path to a branch, for showing it.
+    pub fn id(&self, handle: BranchHandle) -> BranchId {
+        let mut id = BranchId::new(self.plant);
+        let mut branch = &self[handle];
+        while let Some(parent) = branch.parent.and_then(|parent| self.get(parent)) {
+            id.branch_path.push(branch.parent_segment_index);
+            branch = parent;
+        }
+        id.branch_path.reverse();
+        id
+    }
+
+    /// Puts a new branch onto the segment of the parent it says it grows from.
+    pub fn add_branch(&mut self, branch: MLBranch) -> BranchHandle {
+        let parent = branch.parent.expect("Only the main root has no parent");
+        let index = branch.parent_segment_index;
+        if self[parent].segments[index].branch.is_some() {
+            panic!("add_branch - already have a branch");
+        }
+
+        let handle = match self.free.pop() {
+            Some(free) => {
+                let slot = &mut self.slots[free as usize];
+                slot.branch = Some(branch);
+                BranchHandle { index: free, generation: slot.generation }
+            }
+            None => {
+                self.slots.push(Slot { generation: 0, branch: Some(branch) });
+                BranchHandle { index: self.slots.len() as u32 - 1, generation: 0 }
             }
+        };
+        self[parent].segments[index].branch = Some(handle);
+        handle
+    }
+
+    /// Cuts a branch off its parent, if it's still on, and drops it with its subtree.
+    /// All their handles point nowhere from then on. The main root can't be removed.
+    pub fn remove(&mut self, handle: BranchHandle) {
+        if handle == Self::MAIN {
+            return;
+        }
+        let Some(slot) = self.slots.get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation) else {
+            return;
+        };
+        let Some(branch) = slot.branch.take() else {
+            return;
+        };
+        slot.generation += 1;
+        self.free.push(handle.index);
+
+        if let Some(parent) = branch.parent.and_then(|parent| self.get_mut(parent)) {
+            let segment = &mut parent.segments[branch.parent_segment_index];
+            if segment.branch == Some(handle) {
+                segment.branch = None;
+            }
+        }
+        for child in branch.children() {
+            self.remove(child);
         }
+    }
 
-        Some(branch)
+    fn get_child_angle(&self, handle: BranchHandle, index: usize) -> f32 {
+        let segment = &self[handle].segments[index];
+        let child = segment.branch.expect("Branch expected");
+        segment.vec().angle_between(self[child].segments[0].vec())
     }
 
-    fn get_child_angle(&self, index: usize) -> f32 {
-        let my_direction = self.segments[index].vec();
-        let child_direction = self.segments[index].branch
-            .as_ref()
-            .expect("Branch expected")
-            .segments[0]
-            .vec();
-        my_direction.angle_between(child_direction)
+    /// Appends (intake per weight, upkeep) of every branch in the subtree:
+    /// the branch itself, followed by the subtrees of its segments' branches.
+    pub fn upkeep_demands(&self, handle: BranchHandle, demands: &mut Vec<(f32, f32)>) {
+        let branch = &self[handle];
+        demands.push((branch.intake / branch.weight, branch.upkeep()));
+        for child in branch.children() {
+            self.upkeep_demands(child, demands);
+        }
     }
 
-    fn last_branch_index(&self) -> Option<usize> {
-        self.segments.iter().enumerate()
-            .filter_map(|(i, s)| s.branch.as_ref().map(|_| i))
-            .last()
+    /// Ages the subtree and lets every branch wither or recover, depending on its upkeep supply.
+    /// Branches that withered to death are cut off their parents, and their handles go into `dead`.
+    /// They stay in the tree, for the caller to see what died, until `remove`d.
+    /// * `supply` - the share of upkeep paid to each branch, in the order of `upkeep_demands`.
+    pub fn maintain(
+        &mut self,
+        handle: BranchHandle,
+        supply: &mut impl Iterator<Item = f32>,
+        dead: &mut Vec<BranchHandle>,
+    ) {
+        let paid = supply.next().expect("Fewer supplies than branches");
+        let branch = &mut self[handle];
+        branch.age += 1;
+        if paid >= 1.0 {
+            branch.health = f32::min(1.0, branch.health + RECOVERY_RATE);
+        } else {
+            branch.health -= WITHERING_RATE * (1.0 - paid);
+        }
+
+        let children: Vec<BranchHandle> = branch.children().collect();
+        for child in children {
+            self.maintain(child, supply, dead);
+            if self[child].is_dead() {
+                let index = self[child].parent_segment_index;
+                self[handle].segments[index].branch = None;
+                dead.push(child);
+            }
+        }
+
+        self.update_subtree(handle);
+    }
+
+    /// Recomputes `subtree_weight` and the bests from the branch's own fields and its children's.
+    /// Not recursive: the children must be up to date already.
+    fn update_subtree(&mut self, handle: BranchHandle) {
+        let branch = &self[handle];
+        let mut subtree_weight = branch.weight;
+        let mut best_nitro = branch.own_best_nitro;
+        let mut best_water = branch.own_best_water;
+        for child in branch.children().map(|child| &self[child]) {
+            subtree_weight += child.subtree_weight;
+            best_nitro = f32::max(best_nitro, child.best_nitro);
+            best_water = f32::max(best_water, child.best_water);
+        }
+        let branch = &mut self[handle];
+        branch.subtree_weight = subtree_weight;
+        branch.best_nitro = best_nitro;
+        branch.best_water = best_water;
+    }
+
+    /// Checks the invariants of the whole tree: the aggregated fields, the links between
+    /// parents and children, that segments and branches are connected end to start,
+    /// and that every branch in the arena grows from the main root. For debugging and tests.
+    /// * returns the first broken invariant found.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.get(Self::MAIN).is_none_or(|main| main.parent.is_some()) {
+            return Err(format!("Plant {}: no main root", self.plant));
+        }
+        let reached = self.validate_subtree(Self::MAIN)?;
+        let stored = self.slots.iter().filter(|slot| slot.branch.is_some()).count();
+        if reached != stored {
+            return Err(format!("Plant {}: {} branches, but only {} grow from the main root",
+                self.plant, stored, reached));
+        }
+        Ok(())
+    }
+
+    /// * returns the number of branches in the subtree.
+    fn validate_subtree(&self, handle: BranchHandle) -> Result<usize, String> {
+        let fail = |what: String| Err(format!("Branch {}: {}", self.id(handle), what));
+        let branch = &self[handle];
+
+        if branch.segments.is_empty() {
+            return fail("no segments".to_string());
+        }
+        for (i, pair) in branch.segments.windows(2).enumerate() {
+            if pair[0].end != pair[1].start {
+                return fail(format!("segment {} doesn't start where {} ends", i + 1, i));
+            }
+        }
+
+        let mut count = 1;
+        let mut subtree_weight = branch.weight;
+        let mut best_nitro = branch.own_best_nitro;
+        let mut best_water = branch.own_best_water;
+        for (i, segment) in branch.segments.iter().enumerate() {
+            let Some(child_handle) = segment.branch else {
+                continue;
+            };
+            let Some(child) = self.get(child_handle) else {
+                return fail(format!("child at segment {} is gone", i));
+            };
+            if child.parent != Some(handle) || child.parent_segment_index != i {
+                return fail(format!("child at segment {} thinks it grows from {:?}, segment {}",
+                    i, child.parent, child.parent_segment_index));
+            }
+            if child.segments[0].start != segment.end {
+                return fail(format!("child at segment {} is detached from it", i));
+            }
+            count += self.validate_subtree(child_handle)?;
+            subtree_weight += child.subtree_weight;
+            best_nitro = f32::max(best_nitro, child.best_nitro);
+            best_water = f32::max(best_water, child.best_water);
+        }
+
+        if (branch.subtree_weight - subtree_weight).abs() > 1e-4 * subtree_weight.max(1.0) {
+            return fail(format!("subtree weight {} instead of {}", branch.subtree_weight, subtree_weight));
+        }
+        if branch.best_nitro != best_nitro || branch.best_water != best_water {
+            return fail(format!("bests {}/{} instead of {}/{}",
+                branch.best_nitro, branch.best_water, best_nitro, best_water));
+        }
+        Ok(count)
     }
 
-    fn grow_new_branch(&self) -> Option<GrowthDecision> {
+    /// Appends the ends of all the branches in the subtree.
+    pub fn tips(&self, handle: BranchHandle, tips: &mut Vec<Vec2>) {
+        let branch = &self[handle];
+        tips.push(branch.tip());
+        for child in branch.children() {
+            self.tips(child, tips);
+        }
+    }
+
+    /// A child's base can't be thicker than what's left of its parent where it grows from,
+    /// once the other children from there on down are taken out of the parent's cross-section.
+    /// Such a child gets no new material until the parent thickens.
+    fn is_at_thickness_limit(&self, handle: BranchHandle, index: usize) -> bool {
+        let branch = &self[handle];
+        let Some(child) = branch.segments[index].branch else {
+            return false;
+        };
+        let cross_section = |radius: f32| PI * radius * radius;
+        let others: f32 = branch.segments[index + 1..].iter()
+            .filter_map(|segment| segment.branch)
+            .map(|other| cross_section(self[other].segment_radius(0)))
+            .sum();
+        cross_section(self[child].segment_radius(0)) >= cross_section(branch.segment_radius(index)) - others
+    }
+
+    fn grow_new_branch(
+        &self,
+        handle: BranchHandle,
+        soil: &MatrixSoil,
+        roots: &SegmentIndex,
+        strategy: &BranchingStrategy,
+        rng: &mut Rng,
+    ) -> Option<GrowthDecision> {
         // * On one hand, branch interval depends on my size.
         // * On the other hand, the old branches will sit too tight then?..
         // Let's just stick a branch at 1/2 of the remaining length and see!
+        let branch = &self[handle];
 
-        let last_branch_index = self.last_branch_index();
+        let last_branch_index = branch.last_branch_index();
 
         let new_branch_segment = match last_branch_index {
-            None => self.segments.len() / 2,
-            Some(index) if index + 2 >= self.segments.len() => return None,
-            Some(index) => index + (self.segments.len() - index) / 2,
+            None => branch.segments.len() / 2,
+            Some(index) if index + 2 >= branch.segments.len() => return None,
+            Some(index) => index + (branch.segments.len() - index) / 2,
         };
 
-        if new_branch_segment >= self.segments.len() || self.segments[new_branch_segment].branch.is_some() {
+        if new_branch_segment >= branch.segments.len() || branch.segments[new_branch_segment].branch.is_some() {
             panic!("new_branch_segment={}: something went wrong", new_branch_segment);
         }
 
-        let pseudo_rnd_sign = (new_branch_segment as i32 & 1) * 2 - 1;
-        let new_branch_angle = match last_branch_index {
-            None => (pseudo_rnd_sign as f32) * f32::PI() / 4.0,
-            Some(index) => -self.get_child_angle(index),
-        }
-            + self.segments[new_branch_segment].angle();
-
-        // println!("new_branch_angle: {} from {}", new_branch_angle, self.segments[new_branch_segment].angle());
+        // Children alternate sides, the first one picks at random.
+        let side = match last_branch_index {
+            None if rng.rand(2) == 0 => -1.0,
+            None => 1.0,
+            Some(index) => -self.get_child_angle(handle, index).signum(),
+        };
+        let new_branch_angle = side * strategy.default_side_angle.abs()
+            + branch.segments[new_branch_segment].angle();
 
-        let next_point = self.segments[new_branch_segment].end
-            + vec2(SEGMENT_LENGTH * new_branch_angle.cos(), SEGMENT_LENGTH * new_branch_angle.sin());
+        let start = branch.segments[new_branch_segment].end;
+        let direction = steer(soil, start, Vec2::from_angle(new_branch_angle), strategy, rng);
+        let next_point = find_way(soil, roots, self.plant, strategy, start, start + direction * SEGMENT_LENGTH)?;
 
         Some( GrowthDecision::NewBranch( GrowNewBranch {
             direction: next_point,
@@ -243,9 +718,12 @@ impl MLBranch {
     /// returns: distribution of (decision, weight), where sum of weights equals to 1.0
     pub fn growth_decision(
         &self,
-        _soil: &MatrixSoil,
+        handle: BranchHandle,
+        soil: &MatrixSoil,
+        roots: &SegmentIndex,
         _new_material: f32,
-        strategy: &BranchingStrategy
+        strategy: &BranchingStrategy,
+        rng: &mut Rng,
     ) -> Vec<(GrowthDecision, f32)>
     {
         // c = children's share
@@ -263,15 +741,16 @@ impl MLBranch {
         let min_child_mass: f32 = 1.0;
         let min_mass_for_children = min_child_mass / strategy.child_weight_rate;
 
-        let last_branch_index = self.last_branch_index();
+        let branch = &self[handle];
+        let last_branch_index = branch.last_branch_index();
 
         let mut child_decisions: Vec<_> = vec![];
-        if self.weight > min_mass_for_children {
+        if branch.weight > min_mass_for_children {
             // TODO: Move this magic number into the strategy?
             if last_branch_index.is_none()
-                || (last_branch_index.unwrap() as f32 / self.segments.len() as f32) < 0.3
+                || (last_branch_index.unwrap() as f32 / branch.segments.len() as f32) < 0.3
             {
-                if let Some(decision) = self.grow_new_branch() {
+                if let Some(decision) = self.grow_new_branch(handle, soil, roots, strategy, rng) {
                     child_decisions = vec![ (decision, children_share) ];
                 }
             }
@@ -280,17 +759,27 @@ impl MLBranch {
 
         if child_decisions.is_empty() {
 
-            // FIXME: Use local water as a limiting factor instead. This needs a "water grid" in soil.
-            let branch_resources: Vec<f32> = self.segments.iter()
-                .map(|s|
-                    s.branch.as_ref().map(|br| br.best_nitro + br.best_water).unwrap_or_default())
+            // What a child brought, plus what waits around its tip, so that young children
+            // that haven't brought anything yet get a share too.
+            let branch_resources: Vec<f32> = branch.segments.iter()
+                .enumerate()
+                .map(|(i, s)| s.branch
+                    .filter(|_| !self.is_at_thickness_limit(handle, i))
+                    .map(|child| {
+                        let br = &self[child];
+                        let tip = br.tip();
+                        br.best_nitro + br.best_water
+                            + soil.sample_resource(tip, Resource::Nitro)
+                            + soil.sample_resource(tip, Resource::Water)
+                    })
+                    .unwrap_or_default())
                 .collect();
             let total_branch_resources: f32 = branch_resources.iter().sum();
 
             if total_branch_resources > f32::EPSILON {
-                child_decisions = self.segments.iter()
+                child_decisions = branch.segments.iter()
                     .enumerate()
-                    .filter(|(_i, seg)| seg.branch.is_some())
+                    .filter(|(i, seg)| seg.branch.is_some() && branch_resources[*i] > 0.0)
                     .map(|(i, _seg)| (
                         GrowthDecision::Child( GrowChild(i) ),
                         children_share * branch_resources[i] / total_branch_resources
@@ -304,12 +793,19 @@ impl MLBranch {
 
         let my_share = if result.is_empty() { 1.0 } else { 1.0 - children_share };
 
-        let my_decision = if self.get_length() / self.get_radius() < strategy.conic_ratio {
-            let last_segment = self.segments.last().unwrap();
-            let next_point = last_segment.end + (last_segment.end - last_segment.start);
-            GrowthDecision::Longer(GrowLonger(next_point))
+        let last_segment = branch.segments.last().unwrap();
+        let next_point = if branch.get_length() / branch.get_radius() < strategy.conic_ratio {
+            let direction = steer(soil, last_segment.end, last_segment.vec(), strategy, rng);
+            let next_point = last_segment.end + direction * SEGMENT_LENGTH;
+            find_way(soil, roots, self.plant, strategy, last_segment.end, next_point)
         } else {
-            GrowthDecision::Myself
+            None
+        };
+
+        // A stuck tip can only get thicker.
+        let my_decision = match next_point {
+            Some(next_point) => GrowthDecision::Longer(GrowLonger(next_point)),
+            None => GrowthDecision::Myself,
         };
 
         result.push( (my_decision, my_share) );
@@ -317,99 +813,411 @@ impl MLBranch {
         result
     }
 
-    pub fn grow(
+    /// Appends the uptake requests of the whole subtree: for every segment, each resource
+    /// in the order of `Resource::ALL`, followed by the subtree of the branch growing from that segment.
+    pub fn uptake_requests(&self, handle: BranchHandle, requests: &mut Vec<UptakeRequest>) {
+        let branch = &self[handle];
+        for (i, segment) in branch.segments.iter().enumerate() {
+            let surface = branch.segment_surface(i);
+            for what in Resource::ALL {
+                requests.push(UptakeRequest { pos: segment.end, what, power: UPTAKE_RATE, surface });
+            }
+
+            if let Some(child) = segment.branch {
+                self.uptake_requests(child, requests);
+            }
+        }
+    }
+
+    /// Takes the fulfilled amounts, in the same order `uptake_requests` produced the requests.
+    /// * returns the total of each resource brought by the subtree.
+    pub fn absorb(&mut self, handle: BranchHandle, granted: &mut impl Iterator<Item = f32>) -> Amounts {
+        let mut best_nitro = 0.0;
+        let mut best_water = 0.0;
+        let mut total: Amounts = Default::default();
+        let mut intake = 0.0;
+
+        for i in 0..self[handle].segments.len() {
+            for what in Resource::ALL {
+                let amount = granted.next().expect("Fewer grants than requests");
+                intake += amount;
+                total[what.index()] += amount;
+                match what {
+                    Resource::Nitro if amount > best_nitro => best_nitro = amount,
+                    Resource::Water if amount > best_water => best_water = amount,
+                    _ => {}
+                }
+            }
+
+            if let Some(child) = self[handle].segments[i].branch {
+                let subtree = self.absorb(child, granted);
+                for (sum, amount) in total.iter_mut().zip(subtree) {
+                    *sum += amount;
+                }
+            }
+        }
+        let branch = &mut self[handle];
+        branch.own_best_nitro = best_nitro;
+        branch.own_best_water = best_water;
+        branch.intake = intake;
+        self.update_subtree(handle);
+
+        total
+    }
+}
+
+impl Grow for RootTree {
+    fn grow(
         &mut self,
-        // how much mass this branch or its children can gain.
+        handle: BranchHandle,
         new_material: f32,
         soil: &MatrixSoil,
+        roots: &mut SegmentIndex,
         strategy: &BranchingStrategy,
+        rng: &mut Rng,
     ) {
-        let decision = self.growth_decision(soil, new_material, strategy);
+        let decision = self.growth_decision(handle, soil, roots, new_material, strategy, rng);
 
         for (application, weight) in decision {
             match application {
                 GrowthDecision::Longer(GrowLonger(direction)) if direction.y >= 0.0 => {
-                    let last_segment = self.segments.last()
-                        .expect("Empty branch, really?");
-                    self.segments.push(Segment::new(last_segment.end, direction));
-                    self.weight += new_material * weight;
+                    let branch = &mut self[handle];
+                    branch.segments.push(Segment::new(branch.tip(), direction));
+                    branch.weight += new_material * weight / hardness_cost(soil, direction);
+                    let index = branch.segments.len() - 1;
+                    roots.insert(IndexedSegment::of_branch(self, handle, index));
                 }
 
                 GrowthDecision::NewBranch(
                     GrowNewBranch{ direction, parent_segment_index }
                 ) if direction.y >= 0.0 =>
                     {
-                    let cur_segment = &mut self.segments[parent_segment_index];
-                    if cur_segment.branch.is_some() {
-                        panic!("GrowthDecision::NewBranch - already have a branch");
-                    }
-                    cur_segment.branch = Some(Box::new(
-                        MLBranch::new_branch(
-                            cur_segment.end,
-                            direction,
-                            parent_segment_index,
-                            &self.id,
-                            new_material * weight)));
+                    let child = self.add_branch(MLBranch::new_branch(
+                        self[handle].segments[parent_segment_index].end,
+                        direction,
+                        Some(handle),
+                        parent_segment_index,
+                        new_material * weight / hardness_cost(soil, direction)));
+                    roots.insert(IndexedSegment::of_branch(self, child, 0));
                 }
 
-                GrowthDecision::Child(GrowChild(index)) =>
-                    self.segments[index].branch
-                        .as_mut()
-                        .expect("GrowthDecision::Child - bad index")
-                        .grow(new_material * weight, soil, strategy),
+                GrowthDecision::Child(GrowChild(index)) => {
+                    let child = self[handle].segments[index].branch
+                        .expect("GrowthDecision::Child - bad index");
+                    self.grow(child, new_material * weight, soil, roots, strategy, rng);
+                }
 
-                _ => self.weight += new_material * weight,
+                _ => self[handle].weight += new_material * weight,
             }
+        }
+
+        self.update_subtree(handle);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::PI;
+    use glam::{Vec2, vec2};
+    use crate::model::branch::{Branch, BranchHandle, BranchId, find_way, Grow, MLBranch, RootTree, Segment, steer, step_around};
+    use crate::model::{BranchingStrategy, Crowding, Resource};
+    use crate::model::soil::{IMPASSABLE_HARDNESS, MatrixSoil, Soil};
+    use crate::model::spatial::{IndexedSegment, SegmentIndex};
+    use crate::numeric::Rng;
+
+    const MAIN: BranchHandle = RootTree::MAIN;
+
+    /// Straight tips that care for nothing around them, for the tests to change what they need.
+    fn test_strategy() -> BranchingStrategy {
+        BranchingStrategy {
+            conic_ratio: 60.0,
+            children_weight_rate: 0.5,
+            child_weight_rate: 0.02,
+            default_side_angle: 0.0,
+            gravitropism: 0.0,
+            direction_noise: 0.0,
+            hydrotropism: 0.0,
+            chemotropism: 0.0,
+            crowding: Crowding::Deflect,
+            acid_exudation: 0.0,
+            base_exudation: 0.0,
+        }
+    }
+
+    #[test]
+    fn starving_branch_withers_and_is_detached() {
+        let mut tree = RootTree::new(0, 50.0, 10.0);
+        let child = tree.add_branch(MLBranch::new_branch(vec2(50.0, 1.0), vec2(51.0, 2.0), Some(MAIN), 0, 4.0));
+        tree[MAIN].subtree_weight += 4.0;
 
-            self.subtree_weight += new_material;
+        let mut dead = vec![];
+        tree.maintain(MAIN, &mut [1.0, 0.0].into_iter(), &mut dead);
+        assert!(dead.is_empty());
+        assert!(tree[child].is_withering());
+        assert!(!tree.main().is_withering());
+
+        while dead.is_empty() {
+            tree.maintain(MAIN, &mut [1.0, 0.0].into_iter(), &mut dead);
         }
+        assert_eq!(dead, vec![child]);
+        assert!(tree.main().segments[0].branch.is_none());
+        assert_eq!(tree[child].weight, 4.0);
+        assert_eq!(tree.main().subtree_weight, tree.main().weight);
+        assert_eq!(tree.main().age, tree[child].age);
+
+        tree.remove(child);
+        assert!(tree.get(child).is_none());
+        assert_eq!(tree.validate(), Ok(()));
+    }
 
-        // self.update_bests();
+    #[test]
+    fn handles_outlive_their_neighbours_but_not_themselves() {
+        let mut tree = RootTree::new(3, 50.0, 10.0);
+        for i in 1..6 {
+            tree[MAIN].segments.push(Segment::new(vec2(50.0, i as f32), vec2(50.0, i as f32 + 1.0)));
+        }
+        let first = tree.add_branch(MLBranch::new_branch(vec2(50.0, 2.0), vec2(51.0, 3.0), Some(MAIN), 1, 1.0));
+        let second = tree.add_branch(MLBranch::new_branch(vec2(50.0, 4.0), vec2(49.0, 5.0), Some(MAIN), 3, 1.0));
+        let grandchild = tree.add_branch(MLBranch::new_branch(vec2(51.0, 3.0), vec2(52.0, 4.0), Some(first), 0, 0.5));
+        assert_eq!(tree.id(grandchild), BranchId { plant: 3, branch_path: vec![1, 0] });
+        assert_eq!(tree.id(grandchild).to_string(), "[3]:-1-0");
+
+        // The first child goes with its subtree, the second stays where it was.
+        tree.remove(first);
+        assert!(tree.get(first).is_none() && tree.get(grandchild).is_none());
+        assert!(tree.main().segments[1].branch.is_none());
+        assert_eq!(tree.id(second).branch_path, vec![3]);
+        assert_eq!(tree[second].segments[0].end, vec2(49.0, 5.0));
+
+        // A new branch takes a freed slot, but not the old handles.
+        let third = tree.add_branch(MLBranch::new_branch(vec2(50.0, 6.0), vec2(51.0, 7.0), Some(MAIN), 5, 1.0));
+        assert!(third != first && third != grandchild);
+        assert!(tree.get(first).is_none() && tree.get(grandchild).is_none());
+        assert_eq!(tree.id(third).branch_path, vec![5]);
     }
 
-    /// * returns (nitro, water)
-    pub fn suck(&mut self, soil: &mut MatrixSoil) -> (f32, f32) {
+    #[test]
+    fn tip_turns_around_a_rock() {
+        let mut soil = MatrixSoil::new(100, 100, 10);
+        soil.add_rock(vec2(50.0, 20.0), 5.0, IMPASSABLE_HARDNESS + 1.0);
 
-        // FIXME: The consumption must happen AT THE SAME TIME, not sequentially,
-        // so that branches will compete for resources.
-        // BTW it must happen for multiple plants!
-        // So Plant needs to produce a "request" object, and the Soil will "fulfill"
-        // a batch of them.
+        let passable = |point: Vec2| soil.get_hardness(point) < IMPASSABLE_HARDNESS;
 
-        let mut best_nitro = 0.0;
-        let mut best_water = 0.0;
-        let mut total_nitro = 0.0;
-        let mut total_water = 0.0;
-
-        for segment in self.segments.iter_mut() {
-            let nitro = soil.consume_resource(segment.end, Resource::Nitro, 1.0);
-            total_nitro += nitro;
-            if nitro > best_nitro {
-                best_nitro = nitro;
-            }
-            let water = soil.consume_resource(segment.end, Resource::Water, 1.0);
-            total_water += water;
-            if water > best_water {
-                best_water = water;
-            }
+        let free = step_around(vec2(45.0, 19.5), vec2(45.0, 20.5), passable);
+        assert_eq!(free, Some(vec2(45.0, 20.5)));
+
+        let from = vec2(55.0, 19.5);
+        let turned = step_around(from, vec2(55.0, 20.5), passable).unwrap();
+        assert!(soil.get_hardness(turned) < IMPASSABLE_HARDNESS);
+        assert!((turned.distance(from) - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn suck_potential_follows_the_soil_up_to_the_surface() {
+        let mut soil = MatrixSoil::new(100, 100, 10);
+        let root = MLBranch::new(55.0, 10.0);
+        assert_eq!(root.get_suck_potential(Resource::Water, &soil), 0.0);
+
+        soil.add_resource(vec2(55.0, 0.0), Resource::Water, 0.01);
+        let poor = root.get_suck_potential(Resource::Water, &soil);
+        assert!(poor > 0.0);
+
+        soil.add_resource(vec2(55.0, 0.0), Resource::Water, 100.0);
+        let rich = root.get_suck_potential(Resource::Water, &soil);
+        assert!(rich > poor);
+        let thicker = MLBranch::new(55.0, 40.0);
+        assert!(thicker.get_suck_potential(Resource::Water, &soil) > rich);
+    }
 
-            if let Some(branch) = segment.branch.as_mut() {
-                let (seg_nitro, seg_water) = branch.suck(soil);
-                total_water += seg_water;
-                total_nitro += seg_nitro;
+    #[test]
+    fn growing_tree_keeps_its_invariants() {
+        let mut soil = MatrixSoil::new(200, 200, 10);
+        for y in (0..200).step_by(10) {
+            soil.add_resource(vec2(100.0, y as f32), Resource::Nitro, 1.0);
+            soil.add_resource(vec2(100.0, y as f32), Resource::Water, 1.0);
+        }
+        let strategy = BranchingStrategy { gravitropism: 0.1, direction_noise: 0.1, ..test_strategy() };
+
+        let mut rng = Rng::new(1);
+        let mut tree = RootTree::new(0, 100.0, 10.0);
+        let mut died = 0;
+        // The strip runs dry at some point, so the best ever found.
+        let mut best_nitro: f32 = 0.0;
+        for tick in 0..200 {
+            let mut requests = vec![];
+            tree.uptake_requests(MAIN, &mut requests);
+            tree.absorb(MAIN, &mut soil.fulfill(&requests).into_iter());
+            assert_eq!(tree.validate(), Ok(()), "after absorbing at tick {}", tick);
+            best_nitro = best_nitro.max(tree.main().best_nitro);
+
+            let mut demands = vec![];
+            tree.upkeep_demands(MAIN, &mut demands);
+            // Starve a few branches to death now and then.
+            let supply: Vec<f32> = (0..demands.len())
+                .map(|i| if tick % 50 > 20 && i % 3 == 2 { 0.0 } else { 1.0 })
+                .collect();
+            let mut dead = vec![];
+            tree.maintain(MAIN, &mut supply.into_iter(), &mut dead);
+            died += dead.len();
+            for branch in dead {
+                tree.remove(branch);
             }
+            assert_eq!(tree.validate(), Ok(()), "after maintenance at tick {}", tick);
+
+            tree.grow(MAIN, 5.0, &soil, &mut SegmentIndex::default(), &strategy, &mut rng);
+            assert_eq!(tree.validate(), Ok(()), "after growing at tick {}", tick);
         }
-        self.best_nitro = best_nitro;
-        self.best_water = best_water;
+        assert!(tree.main().branch_count() > 0);
+        assert!(died > 0);
+        assert!(best_nitro > 0.0);
+    }
 
-        (total_nitro, total_water)
+    #[test]
+    fn validation_catches_a_broken_subtree() {
+        let mut tree = RootTree::new(0, 50.0, 10.0);
+        let child = tree.add_branch(MLBranch::new_branch(vec2(50.0, 1.0), vec2(51.0, 2.0), Some(MAIN), 0, 4.0));
+        assert!(tree.validate().is_err());
+
+        tree.update_subtree(MAIN);
+        assert_eq!(tree.validate(), Ok(()));
+
+        tree[child].own_best_water = 1.0;
+        assert!(tree.validate().is_err());
+        tree.update_subtree(child);
+        tree.update_subtree(MAIN);
+        assert_eq!(tree.validate(), Ok(()));
+
+        // A branch the main root doesn't know about.
+        tree[MAIN].segments[0].branch = None;
+        tree.update_subtree(MAIN);
+        assert!(tree.validate().is_err());
     }
-}
 
-#[cfg(test)]
-mod test {
+    #[test]
+    fn branch_is_a_cone_twice_as_thick_at_the_base() {
+        let mut tree = RootTree::new(0, 50.0, 30.0);
+        for i in 1..20 {
+            tree[MAIN].segments.push(Segment::new(vec2(50.0, i as f32), vec2(50.0, i as f32 + 1.0)));
+        }
+        let root = tree.main();
+
+        let radii: Vec<f32> = (0..20).map(|i| root.segment_radius(i)).collect();
+        assert!(radii.windows(2).all(|pair| pair[0] > pair[1]));
+        // Middles of the first and the last segment, so not quite 2.
+        assert!((radii[0] / radii[19] - 1.975 / 1.025).abs() < 1e-4);
+        let volume: f32 = radii.iter().map(|r| PI * r * r).sum();
+        assert!((volume - root.weight).abs() < 0.01 * root.weight);
+        assert!(root.get_surface() > 0.0);
+
+        // A child as thick as the parent where it grows from gets nothing more.
+        let child = tree.add_branch(MLBranch::new_branch(vec2(50.0, 5.0), vec2(51.0, 6.0), Some(MAIN), 4, 0.01));
+        assert!(!tree.is_at_thickness_limit(MAIN, 4));
+        tree[child].weight = 30.0;
+        assert!(tree.is_at_thickness_limit(MAIN, 4));
+    }
+
+    #[test]
+    fn siblings_share_the_thickness_of_their_parent() {
+        let mut tree = RootTree::new(0, 50.0, 30.0);
+        for i in 1..20 {
+            tree[MAIN].segments.push(Segment::new(vec2(50.0, i as f32), vec2(50.0, i as f32 + 1.0)));
+        }
+        let upper = tree.add_branch(MLBranch::new_branch(vec2(50.0, 5.0), vec2(51.0, 6.0), Some(MAIN), 4, 0.5));
+        let lower = tree.add_branch(MLBranch::new_branch(vec2(50.0, 9.0), vec2(49.0, 10.0), Some(MAIN), 8, 0.5));
+
+        // Each one alone would fit, but not both together.
+        let parent = tree.main().segment_radius(4);
+        let base = |tree: &RootTree, child| tree[child].segment_radius(0);
+        while base(&tree, upper).powi(2) + base(&tree, lower).powi(2) < parent.powi(2) {
+            tree[upper].weight *= 1.05;
+            tree[lower].weight *= 1.05;
+        }
+        assert!(base(&tree, upper) < parent && base(&tree, lower) < tree.main().segment_radius(8));
+        assert!(tree.is_at_thickness_limit(MAIN, 4));
+        // Only the children further down count, the upper one has nothing to do with the lower.
+        assert!(!tree.is_at_thickness_limit(MAIN, 8));
+    }
 
+    fn grown(strategy: &BranchingStrategy, seed: u64) -> RootTree {
+        let mut soil = MatrixSoil::new(200, 200, 10);
+        // Something to attract the children, so that they grow too.
+        soil.generate(|_| ([1.0; Resource::ALL.len()], 1.0));
+        let mut rng = Rng::new(seed);
+        let mut tree = RootTree::new(0, 100.0, 10.0);
+        for _ in 0..150 {
+            tree.grow(MAIN, 5.0, &soil, &mut SegmentIndex::default(), strategy, &mut rng);
+        }
+        tree
+    }
 
+    /// Average of how much the segments point down, over the whole tree.
+    fn downwardness(tree: &RootTree) -> f32 {
+        let mut sum = 0.0;
+        let mut count = 0;
+        let mut branches = vec![MAIN];
+        while let Some(branch) = branches.pop() {
+            for segment in tree[branch].segments.iter() {
+                sum += segment.vec().normalize().y;
+                count += 1;
+            }
+            branches.extend(tree[branch].children());
+        }
+        assert!(count > 1);
+        sum / count as f32
+    }
 
-}
+    #[test]
+    fn gravitropism_pulls_branches_down() {
+        let strategy = |gravitropism| BranchingStrategy {
+            default_side_angle: PI / 3.0,
+            gravitropism,
+            direction_noise: 0.1,
+            ..test_strategy()
+        };
 
+        let same = (grown(&strategy(0.0), 5), grown(&strategy(0.0), 5));
+        let tips = |tree: &RootTree| { let mut tips = vec![]; tree.tips(MAIN, &mut tips); tips };
+        assert_eq!(tips(&same.0), tips(&same.1));
+        assert!(tips(&same.0).len() > 1);
+
+        assert!(downwardness(&grown(&strategy(0.5), 5)) > downwardness(&same.0) + 0.1);
+    }
+
+    #[test]
+    fn tips_turn_towards_nitro() {
+        let mut soil = MatrixSoil::new(200, 200, 10);
+        soil.add_resource(vec2(108.0, 28.0), Resource::Nitro, 1.0);
+        let strategy = |chemotropism| BranchingStrategy { hydrotropism: 1.0, chemotropism, ..test_strategy() };
+
+        let mut rng = Rng::new(1);
+        let (tip, down) = (vec2(100.0, 20.0), vec2(0.0, 1.0));
+        assert_eq!(steer(&soil, tip, down, &strategy(0.0), &mut rng), down);
+        let turned = steer(&soil, tip, down, &strategy(1.0), &mut rng);
+        assert!(turned.x > 0.0);
+        assert!((turned.length() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn tips_keep_clear_of_roots_the_way_the_strategy_says() {
+        let soil = MatrixSoil::new(100, 100, 10);
+        let mut roots = SegmentIndex::new(vec2(100.0, 100.0));
+        let segment = |plant, start, end| IndexedSegment { plant, branch: MAIN, segment: 0, start, end };
+        // A root of plant 1 lying across the way, and the tip of plant 0 heading into it.
+        roots.insert(segment(1, vec2(40.0, 21.0), vec2(60.0, 21.0)));
+        let (from, to) = (vec2(50.0, 20.0), vec2(50.0, 21.0));
+        roots.insert(segment(0, vec2(50.0, 19.0), from));
+
+        let strategy = |crowding| BranchingStrategy { crowding, ..test_strategy() };
+        let way = |roots: &SegmentIndex, crowding| find_way(&soil, roots, 0, &strategy(crowding), from, to);
+
+        let deflected = way(&roots, Crowding::Deflect).unwrap();
+        assert!(roots.within(deflected, 0.5).iter().all(|segment| segment.end == from));
+        assert!((deflected.distance(from) - 1.0).abs() < 1e-5);
+        assert_eq!(way(&roots, Crowding::Stop), None);
+        assert_eq!(way(&roots, Crowding::Compete), Some(to));
+
+        // Own roots are avoided even when competing.
+        roots.insert(segment(0, vec2(40.0, 21.2), vec2(60.0, 21.2)));
+        assert_ne!(way(&roots, Crowding::Compete), Some(to));
+    }
+}
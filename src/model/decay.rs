@@ -0,0 +1,107 @@
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+use crate::model::branch::{Branch, BranchHandle, RootTree};
+use crate::model::Resource;
+use crate::model::soil::MatrixSoil;
+
+/// How fast dead roots rot, and what they turn into.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Decomposition {
+    /// Share of the remaining mass that decomposes per tick.
+    pub rate: f32,
+    /// Nitro released per unit of decomposed mass.
+    pub nitro_yield: f32,
+    /// Water released per unit of decomposed mass.
+    pub water_yield: f32,
+}
+
+impl Default for Decomposition {
+    fn default() -> Self {
+        Self {
+            rate: 0.01,
+            nitro_yield: 0.05,
+            water_yield: 0.2,
+        }
+    }
+}
+
+/// A dead branch with its subtree, rotting where it used to grow.
+#[derive(Serialize, Deserialize)]
+pub struct Remains {
+    /// The former segments, as (start, end).
+    pub segments: Vec<(Vec2, Vec2)>,
+    pub initial_mass: f32,
+    /// Mass left to decompose.
+    pub mass: f32,
+}
+
+/// Below this, the remains are gone at once.
+const MIN_MASS: f32 = 0.01;
+
+impl Remains {
+    pub fn from_branch(tree: &RootTree, branch: BranchHandle) -> Self {
+        let mut remains = Self { segments: vec![], initial_mass: 0.0, mass: 0.0 };
+        remains.collect(tree, branch);
+        remains.initial_mass = remains.mass;
+        remains
+    }
+
+    fn collect(&mut self, tree: &RootTree, branch: BranchHandle) {
+        self.mass += tree[branch].get_weight();
+        for segment in tree[branch].segments.iter() {
+            self.segments.push((segment.start, segment.end));
+            if let Some(child) = segment.branch {
+                self.collect(tree, child);
+            }
+        }
+    }
+
+    pub fn is_gone(&self) -> bool {
+        self.mass <= 0.0
+    }
+
+    /// Releases this tick's share of nitro and water, evenly along the former segments.
+    pub fn decompose(&mut self, soil: &mut MatrixSoil) {
+        let settings = soil.decomposition.clone();
+        let decomposed = if self.mass < MIN_MASS { self.mass } else { self.mass * settings.rate };
+        self.mass -= decomposed;
+
+        let per_segment = decomposed / self.segments.len() as f32;
+        for (_, end) in self.segments.iter() {
+            soil.add_resource(*end, Resource::Nitro, per_segment * settings.nitro_yield);
+            soil.add_resource(*end, Resource::Water, per_segment * settings.water_yield);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::branch::RootTree;
+    use crate::model::decay::Remains;
+    use crate::model::Resource;
+    use crate::model::soil::{MatrixSoil, Soil};
+
+    #[test]
+    fn remains_rot_into_nitro_and_water() {
+        let mut soil = MatrixSoil::new(100, 100, 10);
+        let mut remains = Remains::from_branch(&RootTree::new(0, 50.0, 10.0), RootTree::MAIN);
+        assert_eq!(remains.mass, 10.0);
+
+        let end = remains.segments[0].1;
+        let ticks = 100;
+        for _ in 0..ticks {
+            remains.decompose(&mut soil);
+        }
+
+        let decomposed = 10.0 - remains.mass;
+        assert!(decomposed > 0.0 && remains.mass > 0.0);
+        let settings = soil.decomposition.clone();
+        assert!((soil.get_resource(end, Resource::Nitro) - decomposed * settings.nitro_yield).abs() < 1e-4);
+        assert!((soil.get_resource(end, Resource::Water) - decomposed * settings.water_yield).abs() < 1e-4);
+
+        while !remains.is_gone() {
+            remains.decompose(&mut soil);
+        }
+    }
+}
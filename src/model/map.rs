@@ -1,61 +1,114 @@
 use glam::{IVec2, vec2};
-use num_traits::FloatConst;
-use crate::model::BranchingStrategy;
+use serde::{Deserialize, Serialize};
+use crate::model::decay::Remains;
 use crate::model::plant::Plant;
-use crate::model::soil::MatrixSoil;
-use crate::numeric::rand;
+use crate::model::scenario::Scenario;
+use crate::model::soil::{MatrixSoil, Soil};
+use crate::model::spatial::SegmentIndex;
+use crate::numeric::Rng;
 
+#[derive(Serialize, Deserialize)]
 pub struct Map {
     pub soil: MatrixSoil,
     pub plants: Vec<Plant>,
     pub size: IVec2,
+    /// Dead roots, rotting into the soil.
+    #[serde(default)]
+    pub remains: Vec<Remains>,
+    /// All the living roots. Not saved, see `index_roots`.
+    #[serde(skip)]
+    pub roots: SegmentIndex,
 }
 
 impl Map {
-    pub fn new(size: IVec2, nitros: usize) -> Self {
-        let width = size.x;
-        let height = size.y;
-
-        let mut soil = MatrixSoil::new(width as usize, height as usize);
-        for _ in 0..nitros {
-            let r = rand(70) as f32 + 10.0;
-            let x = rand(width - 2 * r as i32) + r as i32;
-            let y = rand(height - 2 * r as i32) + r as i32;
-            let pos = vec2(x as f32, y as f32);
-            let weight = rand(10) as f32 + 2.0;
-            soil.add_nitro(pos, r, weight);
-        }
-
-        let strat1 = BranchingStrategy {
-            conic_ratio: 80.0,
-            children_weight_rate: 0.8,
-            child_weight_rate: 0.03,
-            default_side_angle: -f32::PI() / 5.0,
-        };
+    /// The built-in map, see `Scenario::builtin`.
+    pub fn new(size: IVec2, nitros: usize, rng: &mut Rng) -> Self {
+        let scenario = Scenario::builtin(size, nitros, rng);
+        Self::from_scenario(&scenario, rng)
+    }
 
-        let strat2 = BranchingStrategy {
-            conic_ratio: 90.0,
-            children_weight_rate: 0.9,
-            child_weight_rate: 0.07,
-            default_side_angle: -f32::PI() / 5.0,
-        };
+    pub fn from_scenario(scenario: &Scenario, rng: &mut Rng) -> Self {
+        let width = scenario.size.x as f32;
+        let height = scenario.size.y as f32;
 
-        let strat3 = BranchingStrategy {
-            conic_ratio: 60.0,
-            children_weight_rate: 0.5,
-            child_weight_rate: 0.02,
-            default_side_angle: -f32::PI() / 7.0,
-        };
+        let mut soil = MatrixSoil::new(scenario.size.x as usize, scenario.size.y as usize, scenario.cell_size);
+        soil.nitro_seepage = scenario.nitro_seepage.clone();
+        soil.diffusion = scenario.diffusion.clone();
+        soil.decomposition = scenario.decomposition.clone();
 
-        Self {
-            soil,
-            plants: vec![
-                Plant::new(0, 120.0, strat1),
-                Plant::new(1, 240.0, strat2),
-                Plant::new(2, 400.0, strat3)
-            ],
-            size
+        if let Some(profile) = &scenario.profile {
+            let seed = profile.seed.unwrap_or_else(|| rng.next_u32() as u64);
+            profile.generate(&mut soil, seed);
+        }
+
+        for random in scenario.random_deposits.iter() {
+            for _ in 0..random.count {
+                let r = rng.range(random.min_radius, random.max_radius);
+                let pos = vec2(rng.range(r, width - r), rng.range(r, height - r));
+                let weight = rng.range(random.min_weight, random.max_weight);
+                soil.add_deposit(pos, r, random.what, weight);
+            }
+        }
+
+        for deposit in scenario.deposits.iter() {
+            soil.add_deposit(deposit.pos, deposit.radius, deposit.what, deposit.weight);
+        }
+
+        for source in scenario.water_sources.iter() {
+            soil.add_water_source(source.clone());
+        }
+
+        for layer in scenario.hard_layers.iter() {
+            soil.add_hard_layer(layer.top, layer.bottom, layer.hardness);
+        }
+
+        for rock in scenario.rocks.iter() {
+            soil.add_rock(rock.pos, rock.radius, rock.hardness);
         }
+
+        let mut map = Self {
+            soil,
+            plants: scenario.plants.iter()
+                .enumerate()
+                .map(|(id, setup)| Plant::new(id as u32, setup.x, setup.strategy.clone()))
+                .collect(),
+            size: scenario.size,
+            remains: vec![],
+            roots: SegmentIndex::default(),
+        };
+        map.index_roots();
+        map
     }
 
-}
\ No newline at end of file
+    /// Indexes all the living roots from scratch, as after loading.
+    /// From then on, the plants keep the index up to date as they grow and die.
+    pub fn index_roots(&mut self) {
+        self.roots = SegmentIndex::of_plants(self.size.as_vec2(), &self.plants);
+    }
+
+    /// One step of the world: the soil replenishes and dead roots rot,
+    /// then all plants suck resources at once and grow.
+    pub fn tick(&mut self, rng: &mut Rng) {
+        self.soil.tick();
+
+        for remains in self.remains.iter_mut() {
+            remains.decompose(&mut self.soil);
+        }
+        self.remains.retain(|remains| !remains.is_gone());
+
+        let requests: Vec<_> = self.plants.iter()
+            .map(|plant| plant.uptake_requests())
+            .collect();
+        let granted = self.soil.fulfill(&requests.concat());
+
+        let mut offset = 0;
+        for (plant, plant_requests) in self.plants.iter_mut().zip(requests.iter()) {
+            plant.absorb(&granted[offset..offset + plant_requests.len()]);
+            offset += plant_requests.len();
+        }
+
+        for plant in self.plants.iter_mut() {
+            self.remains.extend(plant.grow(&mut self.soil, &mut self.roots, rng));
+        }
+    }
+}
@@ -3,15 +3,64 @@ pub mod branch;
 pub mod soil;
 pub mod plant;
 pub mod map;
+pub mod water;
+pub mod decay;
+pub mod simulation;
+pub mod save;
+pub mod scenario;
+pub mod profile;
+pub mod spatial;
 
-#[derive(Copy, Clone)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Resource {
     Water,
-    Nitro
+    Nitro,
+    Phosphorus,
+    Potassium,
+}
+
+/// An amount of each resource, indexed by `Resource::index`.
+pub type Amounts = [f32; Resource::ALL.len()];
+
+impl Resource {
+    /// A new resource needs a place here, in `relative_need`, in `soil::availability`
+    /// and a diffusion rate in `soil::Diffusion`.
+    pub const ALL: [Resource; 4] = [Resource::Water, Resource::Nitro, Resource::Phosphorus, Resource::Potassium];
+
+    /// Position in `ALL`, and in per-resource arrays.
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// How much of it a plant needs per unit of growth, relative to water.
+    pub fn relative_need(self) -> f32 {
+        match self {
+            Resource::Water => 1.0,
+            Resource::Nitro => 1.0,
+            Resource::Phosphorus => 0.2,
+            Resource::Potassium => 0.5,
+        }
+    }
+}
+
+/// What a growing tip does when it's about to run into a root.
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum Crowding {
+    /// Turns aside, or stops if there's no way around.
+    #[default]
+    Deflect,
+    /// Stops, and the branch gets thicker instead.
+    Stop,
+    /// Turns aside from its own roots, but pushes in among other plants' ones
+    /// and fights for their resources.
+    Compete,
 }
 
 // This will define the shape of the root.
 // Extension idea: Maybe make these dependent on depth or humidity?
+#[derive(Clone, Serialize, Deserialize)]
 pub struct BranchingStrategy {
 
     /// Length:diameter ratio.
@@ -25,11 +74,45 @@ pub struct BranchingStrategy {
     /// one child:my weight ratio.
     pub child_weight_rate: f32,
 
-    /// Angle at which new branch tends to grow, unless it grows downwards.
+    /// Angle between a new branch and its parent. Children alternate sides, so the sign is ignored.
     /// Extension idea: maybe we want entire distribution.
     pub default_side_angle: f32,
 
-    // TODO: Dependency on soil - water/nitro/pH.
+    /// How strongly growing tips turn downwards each segment,
+    /// from 0 (not at all) to 1 (straight down at once).
+    #[serde(default = "BranchingStrategy::default_gravitropism")]
+    pub gravitropism: f32,
+
+    /// The largest random turn of a growing tip each segment, radians.
+    #[serde(default = "BranchingStrategy::default_direction_noise")]
+    pub direction_noise: f32,
+
+    /// How strongly growing tips turn towards water ahead of them.
+    #[serde(default)]
+    pub hydrotropism: f32,
+
+    /// How strongly growing tips turn towards nitro ahead of them.
+    #[serde(default)]
+    pub chemotropism: f32,
+
+    /// What a growing tip does when it runs into another root.
+    #[serde(default)]
+    pub crowding: Crowding,
+
+    /// Share of the production exuded as acid around the root tips, lowering the soil pH.
+    #[serde(default)]
+    pub acid_exudation: f32,
+
+    /// Share of the production exuded as base around the root tips, raising the soil pH.
+    #[serde(default)]
+    pub base_exudation: f32,
+
+    // TODO: Dependency on soil - water/nitro.
 
     // Extension idea: Strength breaking a hard soil(rock)?
 }
+
+impl BranchingStrategy {
+    fn default_gravitropism() -> f32 { 0.1 }
+    fn default_direction_noise() -> f32 { 0.1 }
+}
@@ -1,32 +1,186 @@
-use crate::model::branch::{MLBranch};
-use crate::model::BranchingStrategy;
-use crate::model::soil::MatrixSoil;
+use serde::{Deserialize, Serialize};
+use crate::model::branch::{Grow, RootTree};
+use crate::model::{Amounts, BranchingStrategy, Resource};
+use crate::model::decay::Remains;
+use crate::model::soil::{MatrixSoil, Soil, UptakeRequest};
+use crate::model::spatial::SegmentIndex;
+use crate::numeric::Rng;
 
+#[derive(Serialize, Deserialize)]
 pub struct Plant {
-    pub root: MLBranch,
+    pub root: RootTree,
     pub strategy: BranchingStrategy,
-    pub water_access: f32,
-    pub nitro_access: f32,
+    /// Resources brought by the whole root in the last tick, indexed by `Resource::index`.
+    pub access: Amounts,
 }
 
 impl Plant {
     pub fn new(id: u32, x_coord: f32, strategy: BranchingStrategy) -> Self {
-        let plant = Self {
-            root: MLBranch::new(id, x_coord, 10.0),
+        Self {
+            root: RootTree::new(id, x_coord, 10.0),
             strategy,
-            water_access: 0.0,
-            nitro_access: 0.0
-        };
-        plant
+            access: Default::default(),
+        }
+    }
+
+    /// The plant is dead when its main root is.
+    pub fn is_alive(&self) -> bool {
+        !self.root.main().is_dead()
+    }
+
+    /// Checks the invariants of the whole root, see `RootTree::validate`.
+    pub fn validate(&self) -> Result<(), String> {
+        self.root.validate()
+    }
+
+    pub fn uptake_requests(&self) -> Vec<UptakeRequest> {
+        let mut requests = vec![];
+        if self.is_alive() {
+            self.root.uptake_requests(RootTree::MAIN, &mut requests);
+        }
+        requests
     }
 
-    pub fn grow(&mut self, soil: &mut MatrixSoil) {
-        (self.nitro_access, self.water_access) = self.root.suck(soil);
+    /// * `granted` - the soil's answer to `uptake_requests`, in the same order.
+    pub fn absorb(&mut self, granted: &[f32]) {
+        // A dead plant asked for nothing.
+        if !self.is_alive() {
+            self.access = Default::default();
+            return;
+        }
+        self.access = self.root.absorb(RootTree::MAIN, &mut granted.iter().cloned());
+    }
+
+    /// Pays the upkeep of all branches, and grows with what's left.
+    /// * returns the remains of what died of starvation this tick, including the whole plant.
+    /// * `roots` - all the roots on the map, to keep clear of. Follows what grows and dies here.
+    pub fn grow(&mut self, soil: &mut MatrixSoil, roots: &mut SegmentIndex, rng: &mut Rng) -> Vec<Remains> {
+        if !self.is_alive() {
+            return vec![];
+        }
 
+        // Liebig's law of the minimum, each resource weighted by how much of it is needed.
         // Extension: use sunlight too.
         // hack hack hack  + 0.2
-        let new_cellulose = f32::min(self.nitro_access + 0.2, self.water_access + 0.2) * 10.0;
+        let production = Resource::ALL.iter()
+            .map(|what| self.access[what.index()] / what.relative_need() + 0.2)
+            .fold(f32::MAX, f32::min)
+            * 10.0;
+        let production = production - self.exude(soil, production);
+
+        let mut demands = vec![];
+        self.root.upkeep_demands(RootTree::MAIN, &mut demands);
+        let upkeep: f32 = demands.iter().map(|(_, upkeep)| upkeep).sum();
+
+        let mut dead = vec![];
+        let supply = Self::distribute_upkeep(&demands, production);
+        self.root.maintain(RootTree::MAIN, &mut supply.into_iter(), &mut dead);
+
+        let mut remains = vec![];
+        for branch in dead {
+            roots.remove_branch(&self.root, branch);
+            remains.push(Remains::from_branch(&self.root, branch));
+            self.root.remove(branch);
+        }
+        if !self.is_alive() {
+            roots.remove_branch(&self.root, RootTree::MAIN);
+            remains.push(Remains::from_branch(&self.root, RootTree::MAIN));
+            return remains;
+        }
+
+        let new_cellulose = production - upkeep;
+        if new_cellulose > 0.0 {
+            self.root.grow(RootTree::MAIN, new_cellulose, soil, roots, &self.strategy, rng);
+        }
+        debug_assert_eq!(self.validate(), Ok(()));
+
+        remains
+    }
+
+    /// Spends the strategy's share of production on acid and base, evenly over the root tips.
+    /// * returns the amount spent.
+    fn exude(&self, soil: &mut MatrixSoil, production: f32) -> f32 {
+        // Never more than all of it, whatever the strategy says.
+        let acid_share = self.strategy.acid_exudation.clamp(0.0, 1.0);
+        let base_share = self.strategy.base_exudation.clamp(0.0, 1.0 - acid_share);
+        let acid = production * acid_share;
+        let base = production * base_share;
+        if acid + base <= 0.0 {
+            return 0.0;
+        }
+
+        let mut tips = vec![];
+        self.root.tips(RootTree::MAIN, &mut tips);
+        for tip in tips.iter() {
+            soil.emit_acid(*tip, acid / tips.len() as f32);
+            soil.emit_base(*tip, base / tips.len() as f32);
+        }
+        acid + base
+    }
+
+    /// When the production can't cover everyone, the branches that bring the most
+    /// per unit of weight are paid first, and the useless ones starve.
+    /// * `demands` - (intake per weight, upkeep) of each branch.
+    /// * returns the paid share of each branch's upkeep, in the same order.
+    fn distribute_upkeep(demands: &[(f32, f32)], production: f32) -> Vec<f32> {
+        let mut order: Vec<usize> = (0..demands.len()).collect();
+        order.sort_by(|a, b| demands[*b].0.total_cmp(&demands[*a].0));
+
+        let mut budget = production;
+        let mut supply = vec![0.0; demands.len()];
+        for i in order {
+            let upkeep = demands[i].1;
+            if upkeep <= f32::EPSILON {
+                supply[i] = 1.0;
+                continue;
+            }
+            let paid = f32::min(budget, upkeep);
+            supply[i] = paid / upkeep;
+            budget -= paid;
+        }
+        supply
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::vec2;
+    use crate::model::branch::{Branch, BranchHandle, RootTree};
+    use crate::model::plant::Plant;
+    use crate::model::scenario::Scenario;
+    use crate::model::soil::MatrixSoil;
+    use crate::model::spatial::SegmentIndex;
+    use crate::numeric::Rng;
+
+    fn total_weight(tree: &RootTree, handle: BranchHandle) -> f32 {
+        tree[handle].get_weight() + tree[handle].children().map(|child| total_weight(tree, child)).sum::<f32>()
+    }
+
+    #[test]
+    fn grows_by_the_scarcest_resource_for_its_need() {
+        let mut strategy = Scenario::from_json(include_str!("../../scenarios/three_plants.json")).unwrap()
+            .plants.remove(0).strategy;
+        strategy.acid_exudation = 0.0;
+        strategy.base_exudation = 0.0;
+        // Water, nitro, phosphorus, potassium.
+        let growth = |access| {
+            let mut plant = Plant::new(0, 50.0, strategy.clone());
+            plant.access = access;
+            let before = total_weight(&plant.root, RootTree::MAIN);
+            let mut soil = MatrixSoil::new(100, 100, 10);
+            let mut roots = SegmentIndex::new(vec2(100.0, 100.0));
+            plant.grow(&mut soil, &mut roots, &mut Rng::new(1));
+            total_weight(&plant.root, RootTree::MAIN) - before
+        };
 
-        self.root.grow(new_cellulose, soil, &self.strategy);
+        let plenty = growth([1.0, 1.0, 1.0, 1.0]);
+        let short_of_water = growth([0.5, 1.0, 1.0, 1.0]);
+        assert!(short_of_water < plenty);
+        // A fifth as much phosphorus and half as much potassium are needed as water.
+        for access in [[1.0, 1.0, 0.1, 1.0], [1.0, 1.0, 1.0, 0.25], [1.0, 1.0, 0.1, 0.25]] {
+            assert!((growth(access) - short_of_water).abs() < 1e-4, "{:?}", access);
+        }
+        // Plenty of the rest doesn't make up for the scarcest one.
+        assert_eq!(growth([0.5, 100.0, 100.0, 100.0]), short_of_water);
     }
 }
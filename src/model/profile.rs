@@ -0,0 +1,172 @@
+use glam::{Vec2, vec2};
+use serde::{Deserialize, Serialize};
+
+use crate::model::{Amounts, Resource};
+use crate::model::soil::MatrixSoil;
+use crate::numeric::fractal_noise;
+
+/// What the ground is made of, with the water, nitro and hardness typical for it.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Stratum {
+    /// Rich and soft.
+    Topsoil,
+    /// Holds water, but is hard to get through.
+    Clay,
+    /// Soft, dry and poor.
+    Sand,
+    /// Roots can't get in.
+    Bedrock,
+}
+
+impl Stratum {
+    fn water(&self) -> f32 {
+        match self {
+            Stratum::Topsoil => 0.3,
+            Stratum::Clay => 0.6,
+            Stratum::Sand => 0.1,
+            Stratum::Bedrock => 0.0,
+        }
+    }
+
+    fn nitro(&self) -> f32 {
+        match self {
+            Stratum::Topsoil => 0.1,
+            Stratum::Clay => 0.02,
+            Stratum::Sand => 0.01,
+            Stratum::Bedrock => 0.0,
+        }
+    }
+
+    /// By Mohs' scale, see `Soil::get_hardness`.
+    fn hardness(&self) -> f32 {
+        match self {
+            Stratum::Topsoil => 1.0,
+            Stratum::Clay => 3.0,
+            Stratum::Sand => 1.5,
+            Stratum::Bedrock => 10.0,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Layer {
+    pub stratum: Stratum,
+    /// Share of the map height. The last layer reaches the bottom anyway.
+    pub thickness: f32,
+}
+
+/// A cross-section of the ground: horizontal layers with wavy boundaries,
+/// and water, nitro and hardness varying within each of them.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SoilProfile {
+    /// Seed of the noise. By default it comes from the map's random generator,
+    /// set it to get the same ground whatever the simulation seed is.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// From the top down.
+    pub layers: Vec<Layer>,
+    /// How far the layer boundaries wander up and down, in map units.
+    pub roughness: f32,
+    /// How much water, nitro and hardness vary within a layer, 0 to 1.
+    pub variation: f32,
+    /// Size of the noise features, in map units.
+    pub scale: f32,
+}
+
+impl Default for SoilProfile {
+    fn default() -> Self {
+        Self {
+            seed: None,
+            layers: vec![
+                Layer { stratum: Stratum::Topsoil, thickness: 0.2 },
+                Layer { stratum: Stratum::Clay, thickness: 0.1 },
+                Layer { stratum: Stratum::Sand, thickness: 0.55 },
+                Layer { stratum: Stratum::Bedrock, thickness: 0.15 },
+            ],
+            roughness: 20.0,
+            variation: 0.5,
+            scale: 80.0,
+        }
+    }
+}
+
+impl SoilProfile {
+    /// Octaves of the noise: a few are enough for cells of 10 units.
+    const OCTAVES: u32 = 4;
+
+    /// Fills the soil with the layers, on top of whatever it already has.
+    pub fn generate(&self, soil: &mut MatrixSoil, seed: u64) {
+        let height = soil.size().y;
+        soil.generate(|pos| self.contents(seed, pos, self.stratum_at(seed, pos, height)));
+    }
+
+    /// The layer at `pos`, with the boundaries shifted up and down by the noise.
+    pub fn stratum_at(&self, seed: u64, pos: Vec2, height: f32) -> Option<Stratum> {
+        let mut bottom = 0.0;
+        for (i, layer) in self.layers.iter().enumerate() {
+            bottom += layer.thickness * height;
+            let is_last = i + 1 == self.layers.len();
+            let shift = self.roughness * fractal_noise(seed ^ i as u64, vec2(pos.x / self.scale, 0.0), Self::OCTAVES);
+            if is_last || pos.y < bottom + shift {
+                return Some(layer.stratum);
+            }
+        }
+        None
+    }
+
+    /// * returns the resources and the hardness of the cell at `pos`.
+    fn contents(&self, seed: u64, pos: Vec2, stratum: Option<Stratum>) -> (Amounts, f32) {
+        let mut amounts = Amounts::default();
+        let Some(stratum) = stratum else {
+            return (amounts, 0.0);
+        };
+
+        // Each property gets its own noise, so that they don't all peak in the same spots.
+        let vary = |channel: u64| {
+            let noise = fractal_noise(seed.wrapping_add(channel << 32), pos / self.scale, Self::OCTAVES);
+            1.0 + self.variation * noise
+        };
+        amounts[Resource::Water.index()] = stratum.water() * vary(1);
+        amounts[Resource::Nitro.index()] = stratum.nitro() * vary(2);
+        // Hardness varies less, so that bedrock stays impassable.
+        (amounts, stratum.hardness() * (1.0 + (vary(3) - 1.0) / 4.0))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::vec2;
+    use crate::model::profile::{SoilProfile, Stratum};
+    use crate::model::Resource;
+    use crate::model::soil::{IMPASSABLE_HARDNESS, MatrixSoil, Soil};
+
+    fn generated(seed: u64) -> MatrixSoil {
+        let mut soil = MatrixSoil::new(300, 200, 10);
+        SoilProfile::default().generate(&mut soil, seed);
+        soil
+    }
+
+    #[test]
+    fn layers_go_from_topsoil_to_bedrock() {
+        let profile = SoilProfile::default();
+        let soil = generated(3);
+        for x in (0..300).step_by(10) {
+            let top = vec2(x as f32, 0.0);
+            let bottom = vec2(x as f32, 199.0);
+            assert_eq!(profile.stratum_at(3, top, 200.0), Some(Stratum::Topsoil));
+            assert_eq!(profile.stratum_at(3, bottom, 200.0), Some(Stratum::Bedrock));
+            assert!(soil.get_hardness(top) < IMPASSABLE_HARDNESS);
+            assert!(soil.get_hardness(bottom) >= IMPASSABLE_HARDNESS);
+            assert!(soil.get_resource(top, Resource::Nitro) > soil.get_resource(bottom, Resource::Nitro));
+        }
+    }
+
+    #[test]
+    fn same_seed_same_ground() {
+        let at = |soil: &MatrixSoil, x, y| soil.get_resource(vec2(x, y), Resource::Water);
+        let (first, second, other) = (generated(3), generated(3), generated(4));
+        let positions: Vec<(f32, f32)> = (0..30).map(|i| (i as f32 * 10.0, 35.0 + i as f32 * 4.0)).collect();
+        assert!(positions.iter().all(|&(x, y)| at(&first, x, y) == at(&second, x, y)));
+        assert!(positions.iter().any(|&(x, y)| at(&first, x, y) != at(&other, x, y)));
+    }
+}
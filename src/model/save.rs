@@ -0,0 +1,339 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::model::simulation::Simulation;
+use crate::model::soil::{DEFAULT_PH, SOFT_SOIL};
+
+/// Bump this whenever the saved structures change, and add a step to `MIGRATIONS`.
+/// New fields with a `#[serde(default)]` don't need it.
+pub const SAVE_VERSION: u64 = 6;
+
+/// `MIGRATIONS[i]` turns a save of version `i + 1` into version `i + 2`.
+const MIGRATIONS: [fn(Value) -> Value; (SAVE_VERSION - 1) as usize] = [
+    add_hardness,
+    add_ph,
+    merge_resources,
+    compact_grids,
+    arena_roots,
+];
+
+/// v2: the soil got a hardness grid.
+fn add_hardness(save: Value) -> Value {
+    add_soil_grid(save, "hardness", SOFT_SOIL)
+}
+
+/// v3: the soil got a pH grid.
+fn add_ph(save: Value) -> Value {
+    add_soil_grid(save, "ph", DEFAULT_PH)
+}
+
+/// v4: phosphorus and potassium joined water and nitro, and all of them went into
+/// per-resource arrays, in the order of `Resource::ALL`.
+fn merge_resources(mut save: Value) -> Value {
+    if let Some(soil) = save.pointer_mut("/simulation/map/soil").and_then(Value::as_object_mut) {
+        let water = soil.remove("water").unwrap_or(Value::Null);
+        let nitro = soil.remove("nitro").unwrap_or(Value::Null);
+        let cells = water.as_array().map(Vec::len).unwrap_or_default();
+        let empty = Value::from(vec![0.0; cells]);
+        soil.insert("resources".to_string(), Value::from(vec![water, nitro, empty.clone(), empty]));
+    }
+
+    if let Some(plants) = save.pointer_mut("/simulation/map/plants").and_then(Value::as_array_mut) {
+        for plant in plants.iter_mut().filter_map(Value::as_object_mut) {
+            let water = plant.remove("water_access").unwrap_or(Value::from(0.0));
+            let nitro = plant.remove("nitro_access").unwrap_or(Value::from(0.0));
+            plant.insert("access".to_string(), Value::from(vec![water, nitro, Value::from(0.0), Value::from(0.0)]));
+        }
+    }
+    save
+}
+
+/// v5: soil grids hold one value per cell, instead of keeping it at the index of the cell's
+/// corner in a grid as big as the map, and `step` got renamed to `cell_size`.
+fn compact_grids(mut save: Value) -> Value {
+    if let Some(soil) = save.pointer_mut("/simulation/map/soil").and_then(Value::as_object_mut) {
+        let dimension = |name: &str| soil.get(name).and_then(Value::as_u64).unwrap_or_default() as usize;
+        let (size_x, size_y) = (dimension("size_x"), dimension("size_y"));
+        let step = soil.remove("step").and_then(|step| step.as_u64()).unwrap_or(10) as usize;
+        soil.insert("cell_size".to_string(), Value::from(step));
+
+        let old_columns = size_x / step;
+        let compact = |grid: &Value, fallback: f32| -> Value {
+            let old = grid.as_array().cloned().unwrap_or_default();
+            let cells: Vec<Value> = (0..size_y.div_ceil(step))
+                .flat_map(|row| (0..size_x.div_ceil(step)).map(move |column| (column, row)))
+                .map(|(column, row)| old.get(step * (column + row * old_columns))
+                    .cloned()
+                    .unwrap_or(Value::from(fallback)))
+                .collect();
+            Value::from(cells)
+        };
+
+        if let Some(resources) = soil.get("resources").and_then(Value::as_array) {
+            let resources: Vec<Value> = resources.iter().map(|grid| compact(grid, 0.0)).collect();
+            soil.insert("resources".to_string(), Value::from(resources));
+        }
+        for (name, fallback) in [("hardness", SOFT_SOIL), ("ph", DEFAULT_PH)] {
+            if let Some(grid) = soil.get(name) {
+                let grid = compact(grid, fallback);
+                soil.insert(name.to_string(), grid);
+            }
+        }
+    }
+    save
+}
+
+/// v6: each plant's branches moved from a tree of nested branches into an arena, see `RootTree`.
+/// Branches lost their path ids, and refer to their parent and children by handles.
+fn arena_roots(mut save: Value) -> Value {
+    if let Some(plants) = save.pointer_mut("/simulation/map/plants").and_then(Value::as_array_mut) {
+        for plant in plants.iter_mut().filter_map(Value::as_object_mut) {
+            let Some(root) = plant.remove("root") else {
+                continue;
+            };
+            let id = root.pointer("/id/plant").cloned().unwrap_or(Value::from(0));
+            let mut slots = vec![];
+            flatten_branch(root, Value::Null, &mut slots);
+            plant.insert("root".to_string(), json!({ "plant": id, "slots": slots, "free": [] }));
+        }
+    }
+    save
+}
+
+/// Moves a nested branch with its subtree into `slots`, each branch after its parent.
+/// * returns the handle of the branch.
+fn flatten_branch(mut branch: Value, parent: Value, slots: &mut Vec<Value>) -> Value {
+    let index = slots.len();
+    let handle = json!({ "index": index, "generation": 0 });
+    slots.push(Value::Null);
+
+    if let Some(fields) = branch.as_object_mut() {
+        fields.remove("id");
+        fields.insert("parent".to_string(), parent);
+    }
+    if let Some(segments) = branch.get_mut("segments").and_then(Value::as_array_mut) {
+        for segment in segments.iter_mut() {
+            let child = segment.get_mut("branch").map(Value::take).unwrap_or(Value::Null);
+            if !child.is_null() {
+                segment["branch"] = flatten_branch(child, handle.clone(), slots);
+            }
+        }
+    }
+    slots[index] = json!({ "generation": 0, "branch": branch });
+    handle
+}
+
+/// Adds a grid of the same size as the resource grids, filled with `value`.
+fn add_soil_grid(mut save: Value, name: &str, value: f32) -> Value {
+    if let Some(soil) = save.pointer_mut("/simulation/map/soil").and_then(Value::as_object_mut) {
+        let cells = soil.get("water").and_then(Value::as_array).map(Vec::len).unwrap_or_default();
+        soil.insert(name.to_string(), Value::from(vec![value; cells]));
+    }
+    save
+}
+
+#[derive(Serialize)]
+struct SaveFile<'a> {
+    version: u64,
+    simulation: &'a Simulation,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(std::io::Error),
+    Format(serde_json::Error),
+    /// The save is newer than this build, or has no version at all.
+    Version(Option<u64>),
+}
+
+impl Display for SaveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveError::Io(e) => write!(f, "can't access the save file: {}", e),
+            SaveError::Format(e) => write!(f, "broken save file: {}", e),
+            SaveError::Version(Some(v)) => write!(f, "unsupported save version {}", v),
+            SaveError::Version(None) => write!(f, "save file has no version"),
+        }
+    }
+}
+
+impl From<std::io::Error> for SaveError {
+    fn from(e: std::io::Error) -> Self { SaveError::Io(e) }
+}
+
+impl From<serde_json::Error> for SaveError {
+    fn from(e: serde_json::Error) -> Self { SaveError::Format(e) }
+}
+
+pub fn to_json(simulation: &Simulation) -> Result<String, SaveError> {
+    Ok(serde_json::to_string(&SaveFile { version: SAVE_VERSION, simulation })?)
+}
+
+/// Reads a save of this or any older version.
+pub fn from_json(json: &str) -> Result<Simulation, SaveError> {
+    let mut save: Value = serde_json::from_str(json)?;
+
+    let version = save.get("version").and_then(Value::as_u64);
+    let mut version = match version {
+        Some(v) if (1..=SAVE_VERSION).contains(&v) => v,
+        _ => return Err(SaveError::Version(version)),
+    };
+
+    while version < SAVE_VERSION {
+        save = MIGRATIONS[(version - 1) as usize](save);
+        version += 1;
+    }
+
+    let simulation = save.get_mut("simulation")
+        .map(Value::take)
+        .unwrap_or(Value::Null);
+    let mut simulation: Simulation = serde_json::from_value(simulation)?;
+    simulation.map.index_roots();
+    Ok(simulation)
+}
+
+pub fn save(simulation: &Simulation, path: impl AsRef<Path>) -> Result<(), SaveError> {
+    fs::write(path, to_json(simulation)?)?;
+    Ok(())
+}
+
+pub fn load(path: impl AsRef<Path>) -> Result<Simulation, SaveError> {
+    from_json(&fs::read_to_string(path)?)
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{ivec2, vec2};
+    use serde_json::{json, Value};
+    use crate::model::branch::{Branch, RootTree};
+    use crate::model::Resource;
+    use crate::model::save::{from_json, SaveError, to_json};
+    use crate::model::simulation::Simulation;
+    use crate::model::soil::{DEFAULT_PH, Soil, SOFT_SOIL};
+
+    #[test]
+    fn round_trip_continues_identically() {
+        let mut original = Simulation::new(7, ivec2(200, 150), 20);
+        for _ in 0..30 {
+            original.step();
+        }
+
+        let json = to_json(&original).unwrap();
+        let mut loaded = from_json(&json).unwrap();
+        assert_eq!(loaded.tick, 30);
+        assert_eq!(to_json(&loaded).unwrap(), json);
+
+        for _ in 0..10 {
+            original.step();
+            loaded.step();
+        }
+        assert_eq!(to_json(&loaded).unwrap(), to_json(&original).unwrap());
+    }
+
+    /// Turns the roots of a save back into trees of nested branches, the way v5 kept them.
+    fn nest_roots(save: &mut Value) {
+        fn nested(slots: &Value, handle: &Value, id: &Value) -> Value {
+            let mut branch = slots[handle["index"].as_u64().unwrap() as usize]["branch"].clone();
+            let fields = branch.as_object_mut().unwrap();
+            fields.remove("parent");
+            fields.insert("id".to_string(), id.clone());
+            for (i, segment) in branch["segments"].as_array_mut().unwrap().iter_mut().enumerate() {
+                if !segment["branch"].is_null() {
+                    let mut child_id = id.clone();
+                    child_id["branch_path"].as_array_mut().unwrap().push(Value::from(i));
+                    segment["branch"] = nested(slots, &segment["branch"], &child_id);
+                }
+            }
+            branch
+        }
+
+        for plant in save["simulation"]["map"]["plants"].as_array_mut().unwrap() {
+            let root = &plant["root"];
+            let id = json!({ "plant": root["plant"], "branch_path": [] });
+            plant["root"] = nested(&root["slots"], &json!({ "index": 0 }), &id);
+        }
+    }
+
+    #[test]
+    fn migrates_nested_roots() {
+        let mut original = Simulation::new(7, ivec2(200, 150), 20);
+        for _ in 0..60 {
+            original.step();
+        }
+        let mut save: Value = serde_json::from_str(&to_json(&original).unwrap()).unwrap();
+        save["version"] = Value::from(5);
+        nest_roots(&mut save);
+
+        let loaded = from_json(&save.to_string()).unwrap();
+        let branches = |tree: &RootTree| {
+            let mut branches = vec![];
+            let mut todo = vec![RootTree::MAIN];
+            while let Some(handle) = todo.pop() {
+                branches.push((tree.id(handle).to_string(), tree[handle].get_weight(), tree[handle].segments.len()));
+                todo.extend(tree[handle].children());
+            }
+            branches.sort_by(|a, b| a.0.cmp(&b.0));
+            branches
+        };
+        for (plant, original) in loaded.map.plants.iter().zip(original.map.plants.iter()) {
+            assert_eq!(plant.validate(), Ok(()));
+            assert_eq!(branches(&plant.root), branches(&original.root));
+        }
+        assert!(loaded.map.plants.iter().any(|plant| branches(&plant.root).len() > 1));
+        assert_eq!(loaded.map.roots.len(), original.map.roots.len());
+    }
+
+    #[test]
+    fn migrates_v1() {
+        let simulation = Simulation::new(7, ivec2(200, 150), 20);
+        let mut save: Value = serde_json::from_str(&to_json(&simulation).unwrap()).unwrap();
+        save["version"] = Value::from(1);
+        let soil = save["simulation"]["map"]["soil"].as_object_mut().unwrap();
+        soil.remove("hardness");
+        soil.remove("ph");
+        let step = soil.remove("cell_size").unwrap();
+        soil.insert("step".to_string(), step);
+        // v1 kept each cell at the index of its corner in a grid as big as the map.
+        let spread = |grid: &Value| {
+            let mut old = vec![0.0; 200 * 150];
+            for (index, value) in grid.as_array().unwrap().iter().enumerate() {
+                // The corner of cell (index % 20, index / 20) is at x + y * 20 in the old layout.
+                old[10 * index] = value.as_f64().unwrap();
+            }
+            Value::from(old)
+        };
+        let resources = soil.remove("resources").unwrap();
+        soil.insert("water".to_string(), spread(&resources[0]));
+        soil.insert("nitro".to_string(), spread(&resources[1]));
+        for plant in save["simulation"]["map"]["plants"].as_array_mut().unwrap() {
+            let plant = plant.as_object_mut().unwrap();
+            let access = plant.remove("access").unwrap();
+            plant.insert("water_access".to_string(), access[0].clone());
+            plant.insert("nitro_access".to_string(), access[1].clone());
+        }
+        nest_roots(&mut save);
+
+        let loaded = from_json(&save.to_string()).unwrap();
+        assert_eq!(loaded.map.soil.get_hardness(vec2(100.0, 10.0)), SOFT_SOIL);
+        assert_eq!(loaded.map.soil.get_ph(vec2(100.0, 10.0)), DEFAULT_PH);
+        for pos in (0..150).step_by(5).flat_map(|y| (0..200).step_by(5).map(move |x| vec2(x as f32, y as f32))) {
+            for what in Resource::ALL {
+                let expected = if what == Resource::Phosphorus || what == Resource::Potassium {
+                    0.0
+                } else {
+                    simulation.map.soil.get_resource(pos, what)
+                };
+                assert_eq!(loaded.map.soil.get_resource(pos, what), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        assert!(matches!(from_json(r#"{"version": 999, "simulation": null}"#), Err(SaveError::Version(Some(999)))));
+        assert!(matches!(from_json(r#"{"simulation": null}"#), Err(SaveError::Version(None))));
+    }
+}
@@ -0,0 +1,357 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+use glam::{IVec2, Vec2, vec2};
+use num_traits::FloatConst;
+use serde::{Deserialize, Serialize};
+
+use crate::model::{BranchingStrategy, Crowding, Resource};
+use crate::model::decay::Decomposition;
+use crate::model::profile::SoilProfile;
+use crate::model::soil::{DEFAULT_CELL_SIZE, Diffusion, NitroSeepage};
+use crate::model::water::WaterSource;
+use crate::numeric::Rng;
+
+/// Everything needed to set up a map: the soil, its resources and the competing plants.
+/// Stored as JSON, see `scenarios/`.
+#[derive(Serialize, Deserialize)]
+pub struct Scenario {
+    pub size: IVec2,
+    /// Side of a soil cell, see `MatrixSoil::new`.
+    #[serde(default = "Scenario::default_cell_size")]
+    pub cell_size: usize,
+    #[serde(default)]
+    pub nitro_seepage: NitroSeepage,
+    #[serde(default)]
+    pub diffusion: Diffusion,
+    #[serde(default)]
+    pub decomposition: Decomposition,
+    /// Layered ground under everything else, if any.
+    #[serde(default)]
+    pub profile: Option<SoilProfile>,
+    /// Resource blobs scattered at random, in addition to `deposits`.
+    #[serde(default)]
+    pub random_deposits: Vec<RandomDeposits>,
+    #[serde(default)]
+    pub deposits: Vec<Deposit>,
+    #[serde(default)]
+    pub water_sources: Vec<WaterSource>,
+    #[serde(default)]
+    pub rocks: Vec<Rock>,
+    #[serde(default)]
+    pub hard_layers: Vec<HardLayer>,
+    pub plants: Vec<PlantSetup>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RandomDeposits {
+    pub what: Resource,
+    pub count: usize,
+    pub min_radius: f32,
+    pub max_radius: f32,
+    pub min_weight: f32,
+    pub max_weight: f32,
+}
+
+/// A round blob of a resource.
+#[derive(Serialize, Deserialize)]
+pub struct Deposit {
+    pub what: Resource,
+    pub pos: Vec2,
+    pub radius: f32,
+    pub weight: f32,
+}
+
+/// Hardness is by Mohs' scale, see `Soil::get_hardness`.
+#[derive(Serialize, Deserialize)]
+pub struct Rock {
+    pub pos: Vec2,
+    pub radius: f32,
+    pub hardness: f32,
+}
+
+/// A horizontal layer of compacted soil.
+#[derive(Serialize, Deserialize)]
+pub struct HardLayer {
+    pub top: f32,
+    pub bottom: f32,
+    pub hardness: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PlantSetup {
+    pub x: f32,
+    pub strategy: BranchingStrategy,
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(std::io::Error),
+    Format(serde_json::Error),
+    /// Well-formed, but makes no sense as a map.
+    Invalid(String),
+}
+
+impl Display for ScenarioError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScenarioError::Io(e) => write!(f, "can't read the scenario: {}", e),
+            ScenarioError::Format(e) => write!(f, "broken scenario: {}", e),
+            ScenarioError::Invalid(e) => write!(f, "invalid scenario: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for ScenarioError {
+    fn from(e: std::io::Error) -> Self { ScenarioError::Io(e) }
+}
+
+impl From<serde_json::Error> for ScenarioError {
+    fn from(e: serde_json::Error) -> Self { ScenarioError::Format(e) }
+}
+
+impl Scenario {
+    fn default_cell_size() -> usize { DEFAULT_CELL_SIZE }
+
+    pub fn from_json(json: &str) -> Result<Self, ScenarioError> {
+        let scenario: Self = serde_json::from_str(json)?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    /// Catches what would make `Map::from_scenario` build a broken map or panic.
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        let invalid = |e: String| Err(ScenarioError::Invalid(e));
+        if self.size.x <= 0 || self.size.y <= 0 {
+            return invalid(format!("size {} isn't positive", self.size));
+        }
+        if self.cell_size == 0 {
+            return invalid("soil cells can't be of zero size".to_string());
+        }
+        if let Some(profile) = &self.profile {
+            if profile.layers.is_empty() {
+                return invalid("the soil profile has no layers".to_string());
+            }
+            if profile.scale <= 0.0 {
+                return invalid(format!("soil profile scale {} isn't positive", profile.scale));
+            }
+            if profile.roughness < 0.0 {
+                return invalid(format!("soil profile roughness {} is negative", profile.roughness));
+            }
+        }
+        for (i, deposits) in self.random_deposits.iter().enumerate() {
+            if deposits.min_radius > deposits.max_radius || deposits.min_weight > deposits.max_weight {
+                return invalid(format!("random deposits #{} have a minimum over the maximum", i));
+            }
+        }
+        if self.plants.is_empty() {
+            return invalid("there are no plants".to_string());
+        }
+        for plant in self.plants.iter() {
+            if !(0.0..self.size.x as f32).contains(&plant.x) {
+                return invalid(format!("plant at {} is off the map", plant.x));
+            }
+            let (acid, base) = (plant.strategy.acid_exudation, plant.strategy.base_exudation);
+            if !(0.0..=1.0).contains(&acid) || !(0.0..=1.0).contains(&base) || acid + base > 1.0 {
+                return invalid(format!("plant at {} exudes shares {} and {} of its production", plant.x, acid, base));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ScenarioError> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    /// The default game: three plants with different strategies, layered ground,
+    /// an aquifer deep down, a creek meandering close to the surface, a few rocks,
+    /// `nitros` random nitro blobs and a third as many of phosphorus and potassium ones.
+    pub fn builtin(size: IVec2, nitros: usize, rng: &mut Rng) -> Self {
+        let width = size.x as f32;
+        let height = size.y as f32;
+
+        let aquifer_top = height * 0.8;
+        let creek_points = (0..=8)
+            .map(|i| vec2(width * i as f32 / 8.0, height * 0.15 + rng.range(-20.0, 20.0)))
+            .collect();
+
+        let rocks = (0..6)
+            .map(|_| {
+                let radius = rng.range(10.0, 30.0);
+                Rock {
+                    pos: vec2(rng.range(0.0, width), rng.range(height * 0.2, height)),
+                    radius,
+                    hardness: 8.0,
+                }
+            })
+            .collect();
+
+        let strat1 = BranchingStrategy {
+            conic_ratio: 80.0,
+            children_weight_rate: 0.8,
+            child_weight_rate: 0.03,
+            default_side_angle: -f32::PI() / 5.0,
+            gravitropism: 0.1,
+            direction_noise: 0.1,
+            hydrotropism: 1.0,
+            chemotropism: 2.0,
+            crowding: Crowding::Deflect,
+            acid_exudation: 0.0,
+            base_exudation: 0.0,
+        };
+
+        let strat2 = BranchingStrategy {
+            conic_ratio: 90.0,
+            children_weight_rate: 0.9,
+            child_weight_rate: 0.07,
+            default_side_angle: -f32::PI() / 5.0,
+            gravitropism: 0.3,
+            direction_noise: 0.05,
+            hydrotropism: 2.0,
+            chemotropism: 0.5,
+            crowding: Crowding::Compete,
+            acid_exudation: 0.0,
+            base_exudation: 0.0,
+        };
+
+        let strat3 = BranchingStrategy {
+            conic_ratio: 60.0,
+            children_weight_rate: 0.5,
+            child_weight_rate: 0.02,
+            default_side_angle: -f32::PI() / 7.0,
+            gravitropism: 0.02,
+            direction_noise: 0.2,
+            hydrotropism: 0.5,
+            chemotropism: 3.0,
+            crowding: Crowding::Stop,
+            acid_exudation: 0.0,
+            base_exudation: 0.05,
+        };
+
+        Self {
+            size,
+            cell_size: DEFAULT_CELL_SIZE,
+            nitro_seepage: NitroSeepage::default(),
+            diffusion: Diffusion::default(),
+            decomposition: Decomposition::default(),
+            profile: Some(SoilProfile::default()),
+            random_deposits: vec![
+                RandomDeposits {
+                    what: Resource::Nitro,
+                    count: nitros,
+                    min_radius: 10.0,
+                    max_radius: 80.0,
+                    min_weight: 2.0,
+                    max_weight: 12.0,
+                },
+                RandomDeposits {
+                    what: Resource::Phosphorus,
+                    count: nitros / 3,
+                    min_radius: 10.0,
+                    max_radius: 40.0,
+                    min_weight: 0.5,
+                    max_weight: 3.0,
+                },
+                RandomDeposits {
+                    what: Resource::Potassium,
+                    count: nitros / 3,
+                    min_radius: 20.0,
+                    max_radius: 60.0,
+                    min_weight: 1.0,
+                    max_weight: 6.0,
+                },
+            ],
+            deposits: vec![],
+            water_sources: vec![
+                WaterSource::aquifer(aquifer_top, aquifer_top + 30.0, 0.05, 2.0),
+                WaterSource::creek(creek_points, 8.0, 0.1, 3.0),
+            ],
+            rocks,
+            hard_layers: vec![],
+            plants: vec![
+                PlantSetup { x: 120.0, strategy: strat1 },
+                PlantSetup { x: 240.0, strategy: strat2 },
+                PlantSetup { x: 400.0, strategy: strat3 },
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{json, Value};
+    use crate::model::map::Map;
+    use crate::model::scenario::{Scenario, ScenarioError};
+    use crate::numeric::Rng;
+
+    /// The bundled scenario, with one thing changed.
+    fn bundled_with(change: impl FnOnce(&mut Value)) -> Result<Scenario, ScenarioError> {
+        let mut json: Value = serde_json::from_str(include_str!("../../scenarios/three_plants.json")).unwrap();
+        change(&mut json);
+        Scenario::from_json(&json.to_string())
+    }
+
+    #[test]
+    fn bundled_scenario_loads() {
+        let scenario = Scenario::from_json(include_str!("../../scenarios/three_plants.json")).unwrap();
+        let map = Map::from_scenario(&scenario, &mut Rng::new(1));
+        assert_eq!(map.size, scenario.size);
+        assert_eq!(map.plants.len(), 3);
+        assert_eq!(map.soil.water_sources().len(), 2);
+    }
+
+    #[test]
+    fn rejects_a_negative_size() {
+        let result = bundled_with(|json| json["size"] = json!([-680, 550]));
+        assert!(matches!(result, Err(ScenarioError::Invalid(_))));
+    }
+
+    #[test]
+    fn rejects_zero_cell_size() {
+        let result = bundled_with(|json| json["cell_size"] = json!(0));
+        assert!(matches!(result, Err(ScenarioError::Invalid(_))));
+    }
+
+    #[test]
+    fn rejects_a_broken_soil_profile() {
+        for (field, value) in [("layers", json!([])), ("scale", json!(0.0)), ("roughness", json!(-1.0))] {
+            let result = bundled_with(|json| json["profile"][field] = value);
+            assert!(matches!(result, Err(ScenarioError::Invalid(_))), "{}", field);
+        }
+    }
+
+    #[test]
+    fn rejects_random_deposits_with_minimum_over_maximum() {
+        let result = bundled_with(|json| json["random_deposits"][1]["min_radius"] = json!(50.0));
+        assert!(matches!(result, Err(ScenarioError::Invalid(_))));
+        let result = bundled_with(|json| json["random_deposits"][0]["max_weight"] = json!(1.0));
+        assert!(matches!(result, Err(ScenarioError::Invalid(_))));
+    }
+
+    #[test]
+    fn rejects_no_plants() {
+        let result = bundled_with(|json| json["plants"] = json!([]));
+        assert!(matches!(result, Err(ScenarioError::Invalid(_))));
+    }
+
+    #[test]
+    fn rejects_exuding_more_than_everything() {
+        let result = bundled_with(|json| json["plants"][0]["strategy"]["acid_exudation"] = json!(1.5));
+        assert!(matches!(result, Err(ScenarioError::Invalid(_))));
+        let result = bundled_with(|json| json["plants"][1]["strategy"]["base_exudation"] = json!(-0.1));
+        assert!(matches!(result, Err(ScenarioError::Invalid(_))));
+        let result = bundled_with(|json| {
+            json["plants"][2]["strategy"]["acid_exudation"] = json!(0.6);
+            json["plants"][2]["strategy"]["base_exudation"] = json!(0.6);
+        });
+        assert!(matches!(result, Err(ScenarioError::Invalid(_))));
+    }
+
+    #[test]
+    fn rejects_plants_off_the_map() {
+        let result = bundled_with(|json| json["plants"][2]["x"] = json!(700.0));
+        assert!(matches!(result, Err(ScenarioError::Invalid(_))));
+        let result = bundled_with(|json| json["plants"][0]["x"] = json!(-1.0));
+        assert!(matches!(result, Err(ScenarioError::Invalid(_))));
+    }
+}
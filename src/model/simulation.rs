@@ -0,0 +1,128 @@
+use glam::IVec2;
+use serde::{Deserialize, Serialize};
+use crate::model::map::Map;
+use crate::model::scenario::Scenario;
+use crate::numeric::Rng;
+
+/// The whole running world. Frontends only call `step` and read the state.
+#[derive(Serialize, Deserialize)]
+pub struct Simulation {
+    pub map: Map,
+    /// Number of steps made so far.
+    pub tick: u64,
+    /// The only source of randomness of the world.
+    pub rng: Rng,
+}
+
+impl Simulation {
+    /// Generates a new world. The same seed always gives the same world.
+    pub fn new(seed: u64, map_size: IVec2, nitros: usize) -> Self {
+        let mut rng = Rng::new(seed);
+        let map = Map::new(map_size, nitros, &mut rng);
+        Self { map, tick: 0, rng }
+    }
+
+    pub fn from_scenario(seed: u64, scenario: &Scenario) -> Self {
+        let mut rng = Rng::new(seed);
+        let map = Map::from_scenario(scenario, &mut rng);
+        Self { map, tick: 0, rng }
+    }
+
+    pub fn step(&mut self) {
+        self.map.tick(&mut self.rng);
+        self.tick += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{ivec2, vec2};
+    use crate::model::branch::Branch;
+    use crate::model::Resource;
+    use crate::model::scenario::Scenario;
+    use crate::model::simulation::Simulation;
+    use crate::model::soil::Soil;
+    use crate::model::spatial::SegmentIndex;
+
+    #[test]
+    fn runs_headless() {
+        let mut simulation = Simulation::new(1, ivec2(680, 550), 50);
+        let weights: Vec<f32> = simulation.map.plants.iter()
+            .map(|plant| plant.root.main().get_weight())
+            .collect();
+
+        for _ in 0..100 {
+            simulation.step();
+            for plant in simulation.map.plants.iter() {
+                assert_eq!(plant.validate(), Ok(()));
+            }
+            // The index follows the roots as they grow and die.
+            let map = &simulation.map;
+            let rebuilt = SegmentIndex::of_plants(map.size.as_vec2(), &map.plants);
+            assert_eq!(map.roots.len(), rebuilt.len());
+            assert!(rebuilt.iter().all(|segment| map.roots.within(segment.end, 1e-3).contains(&segment)));
+        }
+
+        assert_eq!(simulation.tick, 100);
+        for (plant, weight) in simulation.map.plants.iter().zip(weights) {
+            assert!(plant.root.main().get_weight() > weight);
+        }
+    }
+
+    #[test]
+    fn same_seed_same_world() {
+        let mut first = Simulation::new(42, ivec2(300, 200), 30);
+        let mut second = Simulation::new(42, ivec2(300, 200), 30);
+        for _ in 0..20 {
+            first.step();
+            second.step();
+        }
+
+        for x in (0..300).step_by(10) {
+            for y in (0..200).step_by(10) {
+                let pos = vec2(x as f32, y as f32);
+                for what in [Resource::Water, Resource::Nitro] {
+                    assert_eq!(first.map.soil.get_resource(pos, what), second.map.soil.get_resource(pos, what));
+                }
+            }
+        }
+        for (a, b) in first.map.plants.iter().zip(second.map.plants.iter()) {
+            assert_eq!(a.root.main().segments.len(), b.root.main().segments.len());
+            assert_eq!(a.root.main().get_weight(), b.root.main().get_weight());
+        }
+
+        let first = Simulation::new(42, ivec2(300, 200), 30);
+        let other = Simulation::new(43, ivec2(300, 200), 30);
+        let differs = (0..300).step_by(10)
+            .flat_map(|x| (0..200).step_by(10).map(move |y| vec2(x as f32, y as f32)))
+            .any(|pos| first.map.soil.get_resource(pos, Resource::Nitro) != other.map.soil.get_resource(pos, Resource::Nitro));
+        assert!(differs);
+    }
+
+    #[test]
+    fn goes_on_after_a_plant_dies() {
+        let mut scenario = Scenario::from_json(include_str!("../../scenarios/three_plants.json")).unwrap();
+        // Spending everything on acid leaves nothing for the upkeep.
+        scenario.plants[0].strategy.acid_exudation = 1.0;
+        let mut simulation = Simulation::from_scenario(1, &scenario);
+
+        let mut ticks = 0;
+        while simulation.map.plants[0].is_alive() {
+            simulation.step();
+            ticks += 1;
+            assert!(ticks < 500, "the plant doesn't die");
+        }
+        assert!(simulation.map.roots.iter().all(|segment| segment.plant != 0));
+        assert!(!simulation.map.remains.is_empty());
+
+        for _ in 0..50 {
+            simulation.step();
+            for plant in simulation.map.plants.iter() {
+                assert_eq!(plant.validate(), Ok(()));
+            }
+        }
+        assert!(!simulation.map.plants[0].is_alive());
+        assert!(simulation.map.plants[1..].iter().all(|plant| plant.is_alive()));
+        assert!(simulation.map.roots.iter().all(|segment| segment.plant != 0));
+    }
+}
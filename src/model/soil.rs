@@ -1,50 +1,394 @@
+use std::collections::HashMap;
 use std::f32::consts::PI;
 use glam::Vec2;
+use serde::{Deserialize, Serialize};
 
-use crate::model::Resource;
+use crate::model::{Amounts, Resource};
+use crate::model::decay::Decomposition;
+use crate::model::water::WaterSource;
+
+/// Side of a soil cell in the built-in map.
+pub const DEFAULT_CELL_SIZE: usize = 10;
+
+/// Hardness of ordinary soil, that roots grow through at no extra cost.
+pub const SOFT_SOIL: f32 = 1.0;
+
+/// Roots can't grow into soil this hard, see `Soil::get_hardness`.
+pub const IMPASSABLE_HARDNESS: f32 = 7.0;
+
+/// pH of the soil before the roots change it.
+pub const DEFAULT_PH: f32 = 6.5;
+const MIN_PH: f32 = 3.0;
+const MAX_PH: f32 = 9.0;
+
+/// pH change per unit of acid or base emitted into a cell.
+const PH_PER_EXUDATE: f32 = 0.5;
+
+/// Share of the pH deviation from `DEFAULT_PH` that the soil neutralises per tick.
+const PH_BUFFERING: f32 = 0.01;
+
+/// Share of a resource that roots can take up at a given pH. The rest is locked in the soil.
+pub fn availability(what: Resource, ph: f32) -> f32 {
+    match what {
+        Resource::Water => 1.0,
+        // Best in neutral soil, poor in acidic one and somewhat worse in alkaline one.
+        Resource::Nitro => match ph {
+            _ if ph < 4.0 => 0.2,
+            _ if ph < 6.0 => 0.2 + 0.8 * (ph - 4.0) / 2.0,
+            _ if ph < 7.5 => 1.0,
+            _ if ph < 9.0 => 1.0 - 0.4 * (ph - 7.5) / 1.5,
+            _ => 0.6,
+        },
+        // Locked up on both sides of a narrow neutral window.
+        Resource::Phosphorus => match ph {
+            _ if ph < 4.5 => 0.1,
+            _ if ph < 6.0 => 0.1 + 0.9 * (ph - 4.5) / 1.5,
+            _ if ph < 7.0 => 1.0,
+            _ if ph < 8.5 => 1.0 - 0.8 * (ph - 7.0) / 1.5,
+            _ => 0.2,
+        },
+        // Only suffers in quite acidic soil.
+        Resource::Potassium => match ph {
+            _ if ph < 4.0 => 0.5,
+            _ if ph < 5.5 => 0.5 + 0.5 * (ph - 4.0) / 1.5,
+            _ => 1.0,
+        },
+    }
+}
+
+/// A root segment asking the soil for a resource.
+/// All requests of a tick are fulfilled at once, see `Soil::fulfill`.
+#[derive(Copy, Clone)]
+pub struct UptakeRequest {
+    pub pos: Vec2,
+    pub what: Resource,
+    /// Uptake per unit of surface.
+    pub power: f32,
+    pub surface: f32,
+}
+
+impl UptakeRequest {
+    /// The most this request can take out of the soil.
+    pub fn demand(&self) -> f32 {
+        self.power * self.surface
+    }
+}
 
 pub trait Soil {
-    /// Resource, g/cm3
+    /// Resource, g/cm3, in the cell holding `pos`.
     fn get_resource(&self, pos: Vec2, what: Resource) -> f32;
+    /// Resource, g/cm3, interpolated between the cells around `pos`, so that it changes
+    /// smoothly as `pos` moves.
+    fn sample_resource(&self, pos: Vec2, what: Resource) -> f32;
+    /// Removes up to `power` of the resource from the soil at `pos`,
+    /// from the same cells and in the same proportions as `sample_resource` reads them.
+    /// * returns the amount actually removed.
     fn consume_resource(&mut self, pos: Vec2, what: Resource, power: f32) -> f32;
+    /// Fulfills a batch of requests simultaneously: when a cell can't satisfy everyone,
+    /// it is split between the competitors proportionally to their demand.
+    /// Each request draws from the cells around it like `consume_resource`.
+    /// * returns the granted amount for each request, in the same order.
+    fn fulfill(&mut self, requests: &[UptakeRequest]) -> Vec<f32>;
 
     fn get_ph(&self, pos: Vec2) -> f32;
-    // 0 to 10 by Mahs' scale.
+    // 0 to 10 by Mohs' scale. Roots can't grow into `IMPASSABLE_HARDNESS` and harder.
     fn get_hardness(&self, pos: Vec2) -> f32;
 
-    fn emit_acid(&mut self, pos: Vec2) -> f32;
-    fn emit_base(&mut self, pos: Vec2) -> f32;
+    /// Roots exude `amount` of acid or base to change the pH around them.
+    /// * returns the new pH at `pos`.
+    fn emit_acid(&mut self, pos: Vec2, amount: f32) -> f32;
+    fn emit_base(&mut self, pos: Vec2, amount: f32) -> f32;
+}
+
+
+/// How nitro filters down from the surface.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NitroSeepage {
+    /// Nitro added to every surface cell per tick.
+    pub deposition: f32,
+    /// Share of a cell's nitro that moves one cell down per tick, right below the surface.
+    pub percolation: f32,
+    /// Depth at which percolation is `e` times weaker than at the surface.
+    pub attenuation_depth: f32,
+}
+
+impl Default for NitroSeepage {
+    fn default() -> Self {
+        Self {
+            deposition: 0.002,
+            percolation: 0.05,
+            attenuation_depth: 150.0,
+        }
+    }
 }
 
+/// How resources spread between neighbouring cells.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Diffusion {
+    /// Share of the concentration difference with a neighbour that evens out per tick.
+    pub water: f32,
+    pub nitro: f32,
+    #[serde(default = "Diffusion::default_phosphorus")]
+    pub phosphorus: f32,
+    #[serde(default = "Diffusion::default_potassium")]
+    pub potassium: f32,
+    /// Share of a cell's water that flows one cell down per tick.
+    /// At the bottom of the map it drains away.
+    pub water_gravity: f32,
+}
 
+impl Default for Diffusion {
+    fn default() -> Self {
+        Self {
+            water: 0.2,
+            nitro: 0.05,
+            phosphorus: Self::default_phosphorus(),
+            potassium: Self::default_potassium(),
+            water_gravity: 0.02,
+        }
+    }
+}
+
+impl Diffusion {
+    // Phosphorus hardly moves in the soil, potassium a bit better.
+    fn default_phosphorus() -> f32 { 0.005 }
+    fn default_potassium() -> f32 { 0.03 }
+
+    pub fn rate(&self, what: Resource) -> f32 {
+        match what {
+            Resource::Water => self.water,
+            Resource::Nitro => self.nitro,
+            Resource::Phosphorus => self.phosphorus,
+            Resource::Potassium => self.potassium,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct MatrixSoil {
     size_x: usize,
     size_y: usize,
-    step: usize,
-    water: Vec<f32>,
-    nitro: Vec<f32>,
+    /// Side of a square cell. All positions in a cell share its contents.
+    cell_size: usize,
+    /// A grid per resource, indexed by `Resource::index`.
+    resources: Vec<Vec<f32>>,
+    /// By Mohs' scale, see `Soil::get_hardness`.
+    hardness: Vec<f32>,
+    ph: Vec<f32>,
+    water_sources: Vec<WaterSource>,
+    pub nitro_seepage: NitroSeepage,
+    pub diffusion: Diffusion,
+    #[serde(default)]
+    pub decomposition: Decomposition,
 }
 
 impl MatrixSoil {
-    pub fn new(size_x: usize, size_y: usize) -> Self {
+    /// * `cell_size` - side of a cell; the last row and column may stick out of the map.
+    pub fn new(size_x: usize, size_y: usize, cell_size: usize) -> Self {
+        assert!(cell_size > 0, "Cell size must be positive");
+        let cells = size_x.div_ceil(cell_size) * size_y.div_ceil(cell_size);
         Self {
             size_x,
             size_y,
-            step: 10,
-            water: vec![0.0; size_y * size_x],
-            nitro: vec![0.0; size_y * size_x],
+            cell_size,
+            resources: Resource::ALL.iter().map(|_| vec![0.0; cells]).collect(),
+            hardness: vec![SOFT_SOIL; cells],
+            ph: vec![DEFAULT_PH; cells],
+            water_sources: vec![],
+            nitro_seepage: NitroSeepage::default(),
+            diffusion: Diffusion::default(),
+            decomposition: Decomposition::default(),
+        }
+    }
+
+    pub fn add_water_source(&mut self, source: WaterSource) {
+        self.water_sources.push(source);
+    }
+
+    pub fn water_sources(&self) -> &[WaterSource] {
+        &self.water_sources
+    }
+
+    pub fn size(&self) -> Vec2 {
+        Vec2::new(self.size_x as f32, self.size_y as f32)
+    }
+
+    pub fn cell_size(&self) -> usize {
+        self.cell_size
+    }
+
+    fn columns(&self) -> usize {
+        self.size_x.div_ceil(self.cell_size)
+    }
+
+    fn rows(&self) -> usize {
+        self.size_y.div_ceil(self.cell_size)
+    }
+
+    /// Top left corner of the cell.
+    fn cell_corner(&self, index: usize) -> Vec2 {
+        let columns = self.columns();
+        Vec2::new(
+            (index % columns * self.cell_size) as f32,
+            (index / columns * self.cell_size) as f32,
+        )
+    }
+
+    /// What a cell is taken to be at when something covers it or not.
+    /// The corners can all miss a rock or a creek smaller than the cell.
+    fn cell_centre(&self, index: usize) -> Vec2 {
+        self.cell_corner(index) + Vec2::splat(self.cell_size as f32 / 2.0)
+    }
+
+    /// Changes in the soil that happen on their own, once per tick.
+    pub fn tick(&mut self) {
+        self.refill_water();
+        self.seep_nitro();
+        for what in Resource::ALL {
+            let gravity = if what == Resource::Water { self.diffusion.water_gravity } else { 0.0 };
+            self.diffuse(what, self.diffusion.rate(what), gravity);
+        }
+        self.buffer_ph();
+    }
+
+    fn buffer_ph(&mut self) {
+        for ph in self.ph.iter_mut() {
+            *ph += (DEFAULT_PH - *ph) * PH_BUFFERING;
+        }
+    }
+
+    /// * `change` - negative for acid, positive for base.
+    /// * returns the new pH, or `DEFAULT_PH` outside of the map.
+    fn change_ph(&mut self, pos: Vec2, change: f32) -> f32 {
+        if pos.x < 0.0 || pos.y < 0.0 {
+            return DEFAULT_PH;
+        }
+        match self.get_index(pos.x as usize, pos.y as usize) {
+            Some(index) => {
+                self.ph[index] = (self.ph[index] + change).clamp(MIN_PH, MAX_PH);
+                self.ph[index]
+            }
+            None => DEFAULT_PH,
         }
     }
 
+    /// * `rate` - share of the difference with each neighbour that evens out.
+    /// * `gravity` - share of the cell's content that flows one cell down.
+    fn diffuse(&mut self, what: Resource, rate: f32, gravity: f32) {
+        let (columns, rows) = (self.columns(), self.rows());
+        let old = self.grid(what).clone();
+        let mut delta = vec![0.0; old.len()];
+
+        // Each neighbouring pair is visited once: to the right and below.
+        let mut flow = |from: usize, to: Option<usize>, amount: f32| {
+            delta[from] -= amount;
+            if let Some(to) = to {
+                delta[to] += amount;
+            }
+        };
+
+        // Up to 4 neighbours, so a quarter of the rate per each keeps the cell non-negative.
+        let share = rate / 4.0;
+        for row in 0..rows {
+            for column in 0..columns {
+                let here = column + row * columns;
+
+                if column + 1 < columns {
+                    let right = here + 1;
+                    flow(here, Some(right), share * (old[here] - old[right]));
+                }
+
+                let below = (row + 1 < rows).then_some(here + columns);
+                let diffusion = below.map(|below| share * (old[here] - old[below])).unwrap_or_default();
+                flow(here, below, diffusion + gravity * old[here]);
+            }
+        }
+
+        for (value, change) in self.grid_mut(what).iter_mut().zip(delta) {
+            *value = f32::max(0.0, *value + change);
+        }
+    }
+
+    fn seep_nitro(&mut self) {
+        let columns = self.columns();
+        let rows = self.rows();
+        let cell_size = self.cell_size;
+        let seepage = self.nitro_seepage.clone();
+        let nitro = self.grid_mut(Resource::Nitro);
+
+        // Bottom-up, so that nitro moves at most one cell per tick.
+        for row in (0..rows.saturating_sub(1)).rev() {
+            let depth = (row * cell_size) as f32;
+            let share = seepage.percolation * (-depth / seepage.attenuation_depth).exp();
+            for here in row * columns..(row + 1) * columns {
+                let moved = nitro[here] * share;
+                nitro[here] -= moved;
+                nitro[here + columns] += moved;
+            }
+        }
+
+        for value in nitro[..columns].iter_mut() {
+            *value += seepage.deposition;
+        }
+    }
+
+    fn refill_water(&mut self) {
+        for index in 0..self.hardness.len() {
+            let pos = self.cell_centre(index);
+            let current = self.grid(Resource::Water)[index];
+            let recharge = self.water_sources.iter()
+                .filter(|source| source.covers(pos))
+                .map(|source| source.recharge(current))
+                .fold(0.0, f32::max);
+            if recharge > 0.0 {
+                self.grid_mut(Resource::Water)[index] += recharge;
+            }
+        }
+    }
+
+    /// The (up to) four cells whose centres surround `pos`, with their bilinear weights.
+    /// At the edges of the map the missing neighbours are replaced by the edge cells.
+    /// * returns None outside of the map.
+    fn surrounding_cells(&self, pos: Vec2) -> Option<[(usize, f32); 4]> {
+        if pos.x < 0.0 || pos.y < 0.0 {
+            return None;
+        }
+        self.get_index(pos.x as usize, pos.y as usize)?;
+
+        let cell_size = self.cell_size as f32;
+        let (columns, rows) = (self.columns(), self.rows());
+        // In cells, relative to the centre of the first one.
+        let at = pos / cell_size - 0.5;
+        let (left, top) = (at.x.floor(), at.y.floor());
+        let (dx, dy) = (at.x - left, at.y - top);
+
+        let column = |c: f32| (c.max(0.0) as usize).min(columns - 1);
+        let row = |r: f32| (r.max(0.0) as usize).min(rows - 1);
+        let index = |c: f32, r: f32| column(c) + row(r) * columns;
+
+        Some([
+            (index(left, top), (1.0 - dx) * (1.0 - dy)),
+            (index(left + 1.0, top), dx * (1.0 - dy)),
+            (index(left, top + 1.0), (1.0 - dx) * dy),
+            (index(left + 1.0, top + 1.0), dx * dy),
+        ])
+    }
+
+    /// * returns the cell that holds the position, or None outside of the map.
     fn get_index(&self, x: usize, y: usize) -> Option<usize> {
         if x >= self.size_x || y >= self.size_y { // x < 0 || y < 0 ||
             return None;
         }
 
-        let x = x - x % self.step;
-        let y = y - y % self.step;
+        Some(x / self.cell_size + y / self.cell_size * self.columns())
+    }
+
+    fn grid(&self, what: Resource) -> &Vec<f32> {
+        &self.resources[what.index()]
+    }
 
-        Some(x + y * (self.size_x / self.step))
+    fn grid_mut(&mut self, what: Resource) -> &mut Vec<f32> {
+        &mut self.resources[what.index()]
     }
 
     fn add_at(&mut self, x: usize, y: usize, what: Resource, weight: f32) -> f32 {
@@ -53,10 +397,7 @@ impl MatrixSoil {
             None => return 0.0,
         };
 
-        let arr = match what {
-            Resource::Water => &mut self.water,
-            Resource::Nitro => &mut self.nitro,
-        };
+        let arr = self.grid_mut(what);
 
         let was = arr[index];
         arr[index] += weight;
@@ -74,22 +415,57 @@ impl MatrixSoil {
             None => return 0.0,
         };
 
-        let arr = match what {
-            Resource::Water => &self.water,
-            Resource::Nitro => &self.nitro,
-        };
+        self.grid(what)[index]
+    }
+
+    /// Makes a round rock, or hardens the soil there. Never softens it.
+    pub fn add_rock(&mut self, pos: Vec2, radius: f32, hardness: f32) {
+        self.harden(hardness, |cell| cell.distance_squared(pos) <= radius.powi(2));
+    }
+
+    /// Makes a horizontal compacted layer between two depths. Never softens the soil.
+    pub fn add_hard_layer(&mut self, top: f32, bottom: f32, hardness: f32) {
+        self.harden(hardness, |cell| top <= cell.y && cell.y < bottom);
+    }
+
+    fn harden(&mut self, hardness: f32, covers: impl Fn(Vec2) -> bool) {
+        for index in 0..self.hardness.len() {
+            if covers(self.cell_centre(index)) {
+                self.hardness[index] = f32::max(self.hardness[index], hardness);
+            }
+        }
+    }
+
+    /// Sets up the soil cell by cell, e.g. from a `SoilProfile`.
+    /// * `cell` - the resources to add and the hardness of the cell at the given centre.
+    ///   Like `add_rock`, it never softens the soil.
+    pub fn generate(&mut self, cell: impl Fn(Vec2) -> (Amounts, f32)) {
+        for index in 0..self.hardness.len() {
+            let (amounts, hardness) = cell(self.cell_centre(index));
+            for what in Resource::ALL {
+                self.grid_mut(what)[index] += amounts[what.index()];
+            }
+            self.hardness[index] = f32::max(self.hardness[index], hardness);
+        }
+    }
 
-        arr[index]
+    /// * returns the amount actually added.
+    pub fn add_resource(&mut self, pos: Vec2, what: Resource, amount: f32) -> f32 {
+        if pos.x < 0.0 || pos.y < 0.0 {
+            return 0.0;
+        }
+        self.add_at(pos.x as usize, pos.y as usize, what, amount)
     }
 
-    pub fn add_nitro(&mut self, pos: Vec2, radius: f32, weight: f32) {
-        let points = PI * radius.powi(2) / (self.step.pow(2) as f32);
+    /// Spreads `weight` of a resource evenly over a circle.
+    pub fn add_deposit(&mut self, pos: Vec2, radius: f32, what: Resource, weight: f32) {
+        let points = PI * radius.powi(2) / (self.cell_size.pow(2) as f32);
         let mut weight_left = weight;
 
-        for x in ((pos.x - radius) as usize..(pos.x + radius) as usize).step_by(self.step) {
-            for y in ((pos.y - radius) as usize..(pos.y + radius) as usize).step_by(self.step) {
+        for x in ((pos.x - radius) as usize..(pos.x + radius) as usize).step_by(self.cell_size) {
+            for y in ((pos.y - radius) as usize..(pos.y + radius) as usize).step_by(self.cell_size) {
                 if (x as f32 - pos.x).powi(2) + (y as f32 - pos.y).powi(2) <= radius.powi(2) {
-                    weight_left -= self.add_at(x, y, Resource::Nitro, weight / points);
+                    weight_left -= self.add_at(x, y, what, weight / points);
                     if weight_left < 0.0 {
                         return;
                     }
@@ -97,7 +473,7 @@ impl MatrixSoil {
             }
         }
 
-        self.add_at(pos.x as usize, pos.y as usize, Resource::Nitro, weight_left);
+        self.add_at(pos.x as usize, pos.y as usize, what, weight_left);
     }
 }
 
@@ -106,27 +482,319 @@ impl Soil for MatrixSoil {
         self.get_at(pos.x as usize, pos.y as usize, what)
     }
 
-    fn consume_resource(&mut self, pos: Vec2, what: Resource, _power: f32) -> f32 {
-        let consumed = self.get_resource(pos, what);
-        // let consumed = cmp::min(consumed, power);
-        // let consumed = self.add_at(pos.x as usize, pos.y as usize, what, -consumed);
-        // return -consumed;
-        return consumed
+    fn sample_resource(&self, pos: Vec2, what: Resource) -> f32 {
+        match self.surrounding_cells(pos) {
+            Some(cells) => cells.iter()
+                .map(|&(index, weight)| self.grid(what)[index] * weight)
+                .sum(),
+            None => 0.0,
+        }
+    }
+
+    /// Takes at most `power` of the resource out of the cells around `pos`.
+    /// * returns the amount actually consumed, never more than the pH makes available.
+    fn consume_resource(&mut self, pos: Vec2, what: Resource, power: f32) -> f32 {
+        if power <= 0.0 {
+            return 0.0;
+        }
+        let Some(cells) = self.surrounding_cells(pos) else {
+            return 0.0;
+        };
+
+        // What each cell can give, in the proportions of `sample_resource`.
+        let offered = cells.map(|(index, weight)|
+            (index, self.grid(what)[index] * availability(what, self.ph[index]) * weight));
+        let available: f32 = offered.iter().map(|(_, amount)| amount).sum();
+        if available <= 0.0 {
+            return 0.0;
+        }
+
+        let share = f32::min(1.0, power / available);
+        let grid = self.grid_mut(what);
+        for (index, amount) in offered {
+            grid[index] = f32::max(0.0, grid[index] - amount * share);
+        }
+        available * share
+    }
+
+    fn fulfill(&mut self, requests: &[UptakeRequest]) -> Vec<f32> {
+        let cells: Vec<Option<[(usize, f32); 4]>> = requests.iter()
+            .map(|request| self.surrounding_cells(request.pos))
+            .collect();
+
+        let mut demand: HashMap<(usize, Resource), f32> = HashMap::new();
+        for (request, cells) in requests.iter().zip(cells.iter()) {
+            for &(index, weight) in cells.iter().flatten() {
+                *demand.entry((index, request.what)).or_default() += request.demand() * weight;
+            }
+        }
+
+        // Share of the demand each cell can satisfy.
+        let satisfaction: HashMap<(usize, Resource), f32> = demand.iter()
+            .map(|(&(index, what), &total)| {
+                let available = self.grid(what)[index] * availability(what, self.ph[index]);
+                let share = if total > available { available / total } else { 1.0 };
+                ((index, what), share)
+            })
+            .collect();
+
+        for (&(index, what), &total) in demand.iter() {
+            let value = &mut self.grid_mut(what)[index];
+            *value = f32::max(0.0, *value - total * satisfaction[&(index, what)]);
+        }
+
+        requests.iter().zip(cells.iter())
+            .map(|(request, cells)| cells.iter().flatten()
+                .map(|&(index, weight)| request.demand() * weight * satisfaction[&(index, request.what)])
+                .sum())
+            .collect()
+    }
+
+    fn get_ph(&self, pos: Vec2) -> f32 {
+        if pos.x < 0.0 || pos.y < 0.0 {
+            return DEFAULT_PH;
+        }
+        match self.get_index(pos.x as usize, pos.y as usize) {
+            Some(index) => self.ph[index],
+            None => DEFAULT_PH,
+        }
+    }
+
+    fn get_hardness(&self, pos: Vec2) -> f32 {
+        if pos.x < 0.0 || pos.y < 0.0 {
+            return IMPASSABLE_HARDNESS;
+        }
+        match self.get_index(pos.x as usize, pos.y as usize) {
+            Some(index) => self.hardness[index],
+            None => IMPASSABLE_HARDNESS,
+        }
+    }
+
+    fn emit_acid(&mut self, pos: Vec2, amount: f32) -> f32 {
+        self.change_ph(pos, -amount * PH_PER_EXUDATE)
+    }
+
+    fn emit_base(&mut self, pos: Vec2, amount: f32) -> f32 {
+        self.change_ph(pos, amount * PH_PER_EXUDATE)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use glam::vec2;
+    use crate::model::Resource;
+    use crate::model::soil::{availability, DEFAULT_PH, IMPASSABLE_HARDNESS, MatrixSoil, Soil, UptakeRequest};
+    use crate::model::water::WaterSource;
+
+    #[test]
+    fn consumption_depletes_and_never_goes_negative() {
+        let mut soil = MatrixSoil::new(100, 100, 10);
+        soil.add_deposit(vec2(50.0, 50.0), 1.0, Resource::Nitro, 1.0);
+        // The centre of the cell, where nothing is drawn from the neighbours.
+        let pos = vec2(55.0, 55.0);
+        let before = soil.get_resource(pos, Resource::Nitro);
+        assert!(before > 0.0);
+
+        let consumed = soil.consume_resource(pos, Resource::Nitro, before / 4.0);
+        assert!((consumed - before / 4.0).abs() < f32::EPSILON);
+        assert!((soil.get_resource(pos, Resource::Nitro) - before * 0.75).abs() < 1e-6);
+
+        let consumed = soil.consume_resource(pos, Resource::Nitro, 100.0);
+        assert!((consumed - before * 0.75).abs() < 1e-6);
+        assert_eq!(soil.get_resource(pos, Resource::Nitro), 0.0);
+        assert_eq!(soil.consume_resource(pos, Resource::Nitro, 100.0), 0.0);
+    }
+
+    #[test]
+    fn water_sources_refill_up_to_their_level() {
+        let mut soil = MatrixSoil::new(100, 100, 10);
+        soil.add_water_source(WaterSource::aquifer(40.0, 60.0, 0.3, 1.0));
+        soil.add_water_source(WaterSource::creek(vec![vec2(0.0, 10.0), vec2(100.0, 10.0)], 5.0, 0.5, 2.0));
+        let (aquifer, creek, dry) = (vec2(55.0, 45.0), vec2(35.0, 10.0), vec2(55.0, 85.0));
+        assert!(soil.water_sources()[0].covers(aquifer) && !soil.water_sources()[0].covers(dry));
+        assert!(soil.water_sources()[1].covers(creek) && !soil.water_sources()[1].covers(aquifer));
+
+        soil.add_resource(aquifer, Resource::Water, 1.0);
+        soil.consume_resource(aquifer, Resource::Water, 100.0);
+        assert_eq!(soil.get_resource(aquifer, Resource::Water), 0.0);
+
+        let mut levels = vec![];
+        for _ in 0..5 {
+            soil.refill_water();
+            levels.push(soil.get_resource(aquifer, Resource::Water));
+        }
+        for (level, expected) in levels.iter().zip([0.3, 0.6, 0.9, 1.0, 1.0]) {
+            assert!((level - expected).abs() < 1e-6, "{:?}", levels);
+        }
+        assert!((soil.get_resource(creek, Resource::Water) - 2.0).abs() < 1e-6);
+        assert_eq!(soil.get_resource(dry, Resource::Water), 0.0);
+    }
+
+    #[test]
+    fn contested_cell_is_split_proportionally() {
+        let mut soil = MatrixSoil::new(100, 100, 10);
+        let pos = vec2(55.0, 55.0);
+        soil.add_at(50, 50, Resource::Water, 3.0);
+
+        let request = |surface| UptakeRequest { pos, what: Resource::Water, power: 1.0, surface };
+        let granted = soil.fulfill(&[request(2.0), request(4.0), request(0.0)]);
+
+        assert!((granted[0] - 1.0).abs() < 1e-6);
+        assert!((granted[1] - 2.0).abs() < 1e-6);
+        assert_eq!(granted[2], 0.0);
+        assert!(soil.get_resource(pos, Resource::Water).abs() < 1e-6);
     }
 
-    fn get_ph(&self, _pos: Vec2) -> f32 {
-        5.5
+    #[test]
+    fn nitro_percolates_down_and_is_deposited_on_top() {
+        let mut soil = MatrixSoil::new(100, 100, 10);
+        soil.add_at(50, 0, Resource::Nitro, 1.0);
+        soil.seep_nitro();
+
+        let top = soil.get_resource(vec2(50.0, 0.0), Resource::Nitro);
+        let below = soil.get_resource(vec2(50.0, 10.0), Resource::Nitro);
+        let deposition = soil.nitro_seepage.deposition;
+        assert!(below > 0.0);
+        assert!((top + below - 1.0 - deposition).abs() < 1e-6);
+        assert!((soil.get_resource(vec2(20.0, 0.0), Resource::Nitro) - deposition).abs() < 1e-6);
     }
 
-    fn get_hardness(&self, _pos: Vec2) -> f32 {
-        1.0
+    #[test]
+    fn diffusion_spreads_to_neighbours_and_keeps_the_total() {
+        let mut soil = MatrixSoil::new(100, 100, 10);
+        soil.add_at(50, 50, Resource::Water, 1.0);
+        soil.diffuse(Resource::Water, 0.2, 0.0);
+
+        let at = |x: f32, y: f32| soil.get_resource(vec2(x, y), Resource::Water);
+        assert!((at(50.0, 50.0) - 0.8).abs() < 1e-6);
+        for (x, y) in [(40.0, 50.0), (60.0, 50.0), (50.0, 40.0), (50.0, 60.0)] {
+            assert!((at(x, y) - 0.05).abs() < 1e-6);
+        }
+        assert_eq!(at(40.0, 40.0), 0.0);
     }
 
-    fn emit_acid(&mut self, _pos: Vec2) -> f32 {
-        0.0
+    #[test]
+    fn small_creeks_and_rocks_cover_the_cells_they_pass_through() {
+        let mut soil = MatrixSoil::new(100, 100, 20);
+        soil.add_water_source(WaterSource::creek(vec![vec2(0.0, 10.0), vec2(100.0, 10.0)], 8.0, 0.5, 1.0));
+        soil.add_rock(vec2(30.0, 50.0), 3.0, IMPASSABLE_HARDNESS);
+        soil.refill_water();
+
+        assert_eq!(soil.get_resource(vec2(45.0, 5.0), Resource::Water), 0.5);
+        assert_eq!(soil.get_resource(vec2(45.0, 25.0), Resource::Water), 0.0);
+        assert_eq!(soil.get_hardness(vec2(25.0, 45.0)), IMPASSABLE_HARDNESS);
+        assert!(soil.get_hardness(vec2(45.0, 45.0)) < IMPASSABLE_HARDNESS);
     }
 
-    fn emit_base(&mut self, _pos: Vec2) -> f32 {
-        0.0
+    #[test]
+    fn each_resource_spreads_at_its_own_rate() {
+        let mut soil = MatrixSoil::new(100, 100, 10);
+        soil.add_at(50, 50, Resource::Phosphorus, 1.0);
+        soil.add_at(50, 50, Resource::Potassium, 1.0);
+        soil.tick();
+
+        let next_door = |what| soil.get_resource(vec2(60.0, 50.0), what);
+        for what in [Resource::Phosphorus, Resource::Potassium] {
+            assert!((next_door(what) - soil.diffusion.rate(what) / 4.0).abs() < 1e-6);
+        }
+        // Phosphorus hardly moves, potassium a bit better.
+        assert!(next_door(Resource::Phosphorus) < next_door(Resource::Potassium));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn phosphorus_locks_on_both_sides_and_potassium_only_in_acid() {
+        let (phosphorus, potassium) = (Resource::Phosphorus, Resource::Potassium);
+        assert_eq!(availability(phosphorus, DEFAULT_PH), 1.0);
+        assert_eq!(availability(potassium, DEFAULT_PH), 1.0);
+
+        for acid in [3.0, 4.5, 5.0] {
+            assert!(availability(phosphorus, acid) < availability(potassium, acid));
+            assert!(availability(potassium, acid) < 1.0);
+        }
+        for alkaline in [7.5, 8.5, 9.0] {
+            assert!(availability(phosphorus, alkaline) < 1.0);
+            assert_eq!(availability(potassium, alkaline), 1.0);
+        }
+        assert!(availability(phosphorus, 8.0) > availability(phosphorus, 9.0));
+    }
+
+    #[test]
+    fn acid_locks_nitro() {
+        let mut soil = MatrixSoil::new(100, 100, 10);
+        let pos = vec2(55.0, 55.0);
+        soil.add_resource(pos, Resource::Nitro, 1.0);
+
+        let ph = soil.emit_base(pos, 2.0);
+        assert!(ph > DEFAULT_PH);
+        assert_eq!(availability(Resource::Nitro, ph), 1.0);
+
+        let ph = soil.emit_acid(pos, 10.0);
+        assert_eq!(ph, soil.get_ph(pos));
+        let available = availability(Resource::Nitro, ph);
+        assert!(available < 1.0);
+        assert!((soil.consume_resource(pos, Resource::Nitro, 10.0) - available).abs() < 1e-6);
+        assert!((soil.get_resource(pos, Resource::Nitro) - (1.0 - available)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sampling_interpolates_between_cell_centres() {
+        let mut soil = MatrixSoil::new(100, 100, 10);
+        soil.add_at(50, 50, Resource::Water, 1.0);
+
+        let at = |x: f32, y: f32| soil.sample_resource(vec2(x, y), Resource::Water);
+        assert!((at(55.0, 55.0) - 1.0).abs() < 1e-6);
+        assert!((at(60.0, 55.0) - 0.5).abs() < 1e-6);
+        assert!((at(50.0, 50.0) - 0.25).abs() < 1e-6);
+        assert_eq!(at(70.0, 55.0), 0.0);
+        // A small move means a small change.
+        assert!((at(55.0, 55.0) - at(56.0, 55.0)).abs() <= 0.1 + 1e-6);
+
+        // Halfway between two cells, both give their half of the consumption.
+        soil.add_at(60, 50, Resource::Water, 1.0);
+        let consumed = soil.consume_resource(vec2(60.0, 55.0), Resource::Water, 0.5);
+        assert!((consumed - 0.5).abs() < 1e-6);
+        assert!((soil.get_resource(vec2(55.0, 55.0), Resource::Water) - 0.75).abs() < 1e-6);
+        assert!((soil.get_resource(vec2(65.0, 55.0), Resource::Water) - 0.75).abs() < 1e-6);
+    }
+
+    // Includes sizes that are not a multiple of the cell.
+    const GRIDS: [(usize, usize, usize); 4] = [(100, 100, 10), (95, 43, 10), (30, 20, 1), (50, 31, 7)];
+
+    #[test]
+    fn every_cell_has_its_own_index() {
+        for (size_x, size_y, cell_size) in GRIDS {
+            let soil = MatrixSoil::new(size_x, size_y, cell_size);
+            let mut cells = HashMap::new();
+            for y in 0..size_y {
+                for x in 0..size_x {
+                    let index = soil.get_index(x, y).unwrap();
+                    assert!(index < soil.hardness.len());
+                    let cell = (x / cell_size, y / cell_size);
+                    assert_eq!(*cells.entry(index).or_insert(cell), cell);
+                }
+            }
+            assert_eq!(cells.len(), soil.hardness.len());
+            assert_eq!(soil.get_index(size_x, 0), None);
+            assert_eq!(soil.get_index(0, size_y), None);
+        }
+    }
+
+    #[test]
+    fn neighbours_share_a_cell_unless_on_its_border() {
+        for (size_x, size_y, cell_size) in GRIDS {
+            let soil = MatrixSoil::new(size_x, size_y, cell_size);
+            for y in 0..size_y - 1 {
+                for x in 0..size_x - 1 {
+                    let here = soil.get_index(x, y);
+                    assert_eq!(here == soil.get_index(x + 1, y), (x + 1) % cell_size != 0);
+                    assert_eq!(here == soil.get_index(x, y + 1), (y + 1) % cell_size != 0);
+                }
+            }
+            for index in 0..soil.hardness.len() {
+                let corner = soil.cell_corner(index);
+                assert_eq!(soil.get_index(corner.x as usize, corner.y as usize), Some(index));
+            }
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use glam::Vec2;
+
+use crate::model::branch::{BranchHandle, RootTree, Segment};
+use crate::model::plant::Plant;
+use crate::numeric::{distance_to_segment, segment_touches_rect};
+
+/// Side of a bucket of `SegmentIndex`. Segments are much shorter, so each one lands in a few at most.
+const BUCKET_SIZE: f32 = 10.0;
+
+/// A segment of some plant's root, as the index sees it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexedSegment {
+    pub plant: u32,
+    /// The branch the segment belongs to, in the plant's `RootTree`.
+    pub branch: BranchHandle,
+    /// Index in the branch's segments.
+    pub segment: usize,
+    pub start: Vec2,
+    pub end: Vec2,
+}
+
+impl IndexedSegment {
+    pub fn of_branch(tree: &RootTree, branch: BranchHandle, segment: usize) -> Self {
+        let Segment { start, end, .. } = tree[branch].segments[segment];
+        Self { plant: tree.plant, branch, segment, start, end }
+    }
+
+    pub fn distance(&self, pos: Vec2) -> f32 {
+        distance_to_segment(pos, self.start, self.end)
+    }
+}
+
+/// Segments of all the roots on the map, bucketed by a uniform grid,
+/// so that finding the ones around a point doesn't mean checking them all.
+/// Follows the roots as they grow and die, see `insert_branch` and `remove_branch`.
+#[derive(Default)]
+pub struct SegmentIndex {
+    columns: usize,
+    rows: usize,
+    /// Removed segments leave a hole, to be reused by the next insert.
+    segments: Vec<Option<IndexedSegment>>,
+    holes: Vec<usize>,
+    /// Indexes into `segments` of those passing through each bucket.
+    buckets: Vec<Vec<usize>>,
+}
+
+impl SegmentIndex {
+    pub fn new(size: Vec2) -> Self {
+        let columns = (size.x / BUCKET_SIZE).ceil().max(1.0) as usize;
+        let rows = (size.y / BUCKET_SIZE).ceil().max(1.0) as usize;
+        Self {
+            columns,
+            rows,
+            segments: vec![],
+            holes: vec![],
+            buckets: vec![vec![]; columns * rows],
+        }
+    }
+
+    /// Indexes every segment of the living plants.
+    pub fn of_plants(size: Vec2, plants: &[Plant]) -> Self {
+        let mut index = Self::new(size);
+        for plant in plants.iter().filter(|plant| plant.is_alive()) {
+            index.insert_branch(&plant.root, RootTree::MAIN);
+        }
+        index
+    }
+
+    /// Number of segments indexed.
+    pub fn len(&self) -> usize {
+        self.segments.len() - self.holes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &IndexedSegment> {
+        self.segments.iter().flatten()
+    }
+
+    /// Inserts the segments of the whole subtree.
+    pub fn insert_branch(&mut self, tree: &RootTree, branch: BranchHandle) {
+        for i in 0..tree[branch].segments.len() {
+            self.insert(IndexedSegment::of_branch(tree, branch, i));
+        }
+        for child in tree[branch].children() {
+            self.insert_branch(tree, child);
+        }
+    }
+
+    /// Removes the segments of the whole subtree, say when it died.
+    pub fn remove_branch(&mut self, tree: &RootTree, branch: BranchHandle) {
+        for i in 0..tree[branch].segments.len() {
+            self.remove(&IndexedSegment::of_branch(tree, branch, i));
+        }
+        for child in tree[branch].children() {
+            self.remove_branch(tree, child);
+        }
+    }
+
+    pub fn insert(&mut self, segment: IndexedSegment) {
+        let (min, max) = (segment.start.min(segment.end), segment.start.max(segment.end));
+        let id = match self.holes.pop() {
+            Some(id) => {
+                self.segments[id] = Some(segment);
+                id
+            }
+            None => {
+                self.segments.push(Some(segment));
+                self.segments.len() - 1
+            }
+        };
+        for bucket in self.buckets_around(min, max) {
+            self.buckets[bucket].push(id);
+        }
+    }
+
+    /// Removes the segment of the same branch at the same place, if it's there.
+    pub fn remove(&mut self, segment: &IndexedSegment) {
+        let (min, max) = (segment.start.min(segment.end), segment.start.max(segment.end));
+        let found = self.buckets_around(min, max)
+            .flat_map(|bucket| self.buckets[bucket].iter())
+            .find(|&&id| self.segments[id].as_ref() == Some(segment))
+            .cloned();
+        let Some(id) = found else {
+            return;
+        };
+
+        for bucket in self.buckets_around(min, max) {
+            self.buckets[bucket].retain(|&other| other != id);
+        }
+        self.segments[id] = None;
+        self.holes.push(id);
+    }
+
+    /// Segments closer than `radius` to `pos`, each once.
+    pub fn within(&self, pos: Vec2, radius: f32) -> Vec<&IndexedSegment> {
+        self.find(pos - radius, pos + radius, |segment| segment.distance(pos) < radius)
+    }
+
+    /// Segments passing through the rectangle from `min` to `max`, each once.
+    pub fn in_rect(&self, min: Vec2, max: Vec2) -> Vec<&IndexedSegment> {
+        self.find(min, max, |segment| segment_touches_rect(segment.start, segment.end, min, max))
+    }
+
+    /// The segment closest to `pos`, or None if there are none at all.
+    pub fn nearest(&self, pos: Vec2) -> Option<&IndexedSegment> {
+        if self.is_empty() || !pos.is_finite() {
+            return None;
+        }
+        // Looks ever further, until there's something within reach.
+        let mut radius = BUCKET_SIZE;
+        loop {
+            let nearest = self.within(pos, radius).into_iter()
+                .min_by(|a, b| a.distance(pos).total_cmp(&b.distance(pos)));
+            if nearest.is_some() {
+                return nearest;
+            }
+            radius *= 2.0;
+        }
+    }
+
+    /// Segments in the buckets overlapping the rectangle that pass the test, each once.
+    fn find(&self, min: Vec2, max: Vec2, test: impl Fn(&IndexedSegment) -> bool) -> Vec<&IndexedSegment> {
+        let mut found: Vec<usize> = self.buckets_around(min, max)
+            .flat_map(|bucket| self.buckets[bucket].iter().cloned())
+            .filter(|&id| self.segments[id].as_ref().is_some_and(&test))
+            .collect();
+        found.sort_unstable();
+        found.dedup();
+        found.into_iter().filter_map(|id| self.segments[id].as_ref()).collect()
+    }
+
+    /// Buckets overlapping a rectangle, clamped to the map.
+    fn buckets_around(&self, min: Vec2, max: Vec2) -> impl Iterator<Item = usize> {
+        let columns = self.columns;
+        let (first_column, last_column) = (self.column(min.x), self.column(max.x));
+        // A default index has no buckets at all.
+        let rows = if self.buckets.is_empty() { 0..0 } else { self.row(min.y)..self.row(max.y) + 1 };
+        rows.flat_map(move |row| (first_column..=last_column).map(move |column| column + row * columns))
+    }
+
+    fn column(&self, x: f32) -> usize {
+        ((x / BUCKET_SIZE).max(0.0) as usize).min(self.columns.saturating_sub(1))
+    }
+
+    fn row(&self, y: f32) -> usize {
+        ((y / BUCKET_SIZE).max(0.0) as usize).min(self.rows.saturating_sub(1))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{vec2, Vec2};
+    use crate::model::branch::{MLBranch, RootTree};
+    use crate::model::spatial::{IndexedSegment, SegmentIndex};
+    use crate::numeric::segment_touches_rect;
+
+    fn sorted(mut segments: Vec<&IndexedSegment>) -> Vec<&IndexedSegment> {
+        segments.sort_by(|a, b| a.start.x.total_cmp(&b.start.x).then(a.start.y.total_cmp(&b.start.y)));
+        segments
+    }
+
+    #[test]
+    fn finds_exactly_the_segments_nearby() {
+        let mut index = SegmentIndex::new(vec2(100.0, 100.0));
+        let mut all = vec![];
+        let segment = |plant, start: Vec2, end| IndexedSegment { plant, branch: RootTree::MAIN, segment: 0, start, end };
+        // A diagonal root crossing many buckets, and a few short ones around.
+        for i in 0..90 {
+            let start = vec2(i as f32, i as f32);
+            all.push(segment(0, start, start + Vec2::ONE));
+        }
+        for i in 0..10 {
+            let start = vec2(95.0 - i as f32 * 9.0, 5.0 + i as f32 * 3.0);
+            all.push(segment(1, start, start + vec2(0.0, 1.0)));
+        }
+        for segment in all.iter() {
+            index.insert(segment.clone());
+        }
+        // Removed ones are gone, and their holes get reused.
+        index.remove(&all.remove(50));
+        index.remove(&all.remove(95));
+        let extra = segment(1, vec2(30.0, 70.0), vec2(31.0, 70.5));
+        index.insert(extra.clone());
+        all.push(extra);
+        assert_eq!(index.len(), all.len());
+
+        for pos in [vec2(50.0, 50.0), vec2(59.5, 62.0), vec2(95.0, 5.0), vec2(0.0, 99.0), vec2(31.0, 71.0)] {
+            for radius in [0.5, 3.0, 15.0] {
+                let expected = all.iter().filter(|s| s.distance(pos) < radius).collect();
+                assert_eq!(sorted(index.within(pos, radius)), sorted(expected), "around {} within {}", pos, radius);
+            }
+
+            let nearest = all.iter().map(|s| s.distance(pos)).fold(f32::MAX, f32::min);
+            assert_eq!(index.nearest(pos).map(|s| s.distance(pos)), Some(nearest), "nearest to {}", pos);
+
+            let (min, max) = (pos - vec2(4.0, 2.0), pos + vec2(2.0, 6.0));
+            let expected = all.iter().filter(|s| segment_touches_rect(s.start, s.end, min, max)).collect();
+            assert_eq!(sorted(index.in_rect(min, max)), sorted(expected), "in {} - {}", min, max);
+        }
+
+        // The reference itself: a segment crossing a corner, but with both ends outside.
+        assert!(segment_touches_rect(vec2(0.0, 1.5), vec2(1.5, 0.0), Vec2::ZERO, Vec2::ONE));
+        assert!(!segment_touches_rect(vec2(0.0, 2.5), vec2(2.5, 0.0), Vec2::ZERO, Vec2::ONE));
+        assert_eq!(SegmentIndex::default().nearest(Vec2::ZERO), None);
+    }
+
+    #[test]
+    fn dead_subtree_leaves_the_index() {
+        let mut tree = RootTree::new(0, 50.0, 10.0);
+        let child = tree.add_branch(MLBranch::new_branch(vec2(50.0, 1.0), vec2(51.0, 2.0), Some(RootTree::MAIN), 0, 4.0));
+        let grandchild = tree.add_branch(MLBranch::new_branch(vec2(51.0, 2.0), vec2(52.0, 2.0), Some(child), 0, 1.0));
+
+        let mut index = SegmentIndex::new(vec2(100.0, 100.0));
+        index.insert_branch(&tree, RootTree::MAIN);
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.nearest(vec2(53.0, 2.0)).unwrap().branch, grandchild);
+
+        index.remove_branch(&tree, child);
+        tree.remove(child);
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.nearest(vec2(53.0, 2.0)).unwrap().branch, RootTree::MAIN);
+    }
+}
@@ -0,0 +1,55 @@
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+use crate::numeric::distance_to_segment;
+
+/// Where the water comes from.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum WaterSourceShape {
+    /// A horizontal water-bearing layer between two depths.
+    Aquifer { top: f32, bottom: f32 },
+    /// A creek running along a polyline. Cells within `width` of it get water.
+    Creek { points: Vec<Vec2>, width: f32 },
+}
+
+/// A source of water that never runs dry.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WaterSource {
+    pub shape: WaterSourceShape,
+    /// Water added to each covered cell per tick.
+    pub recharge_rate: f32,
+    /// A cell is not refilled above this level.
+    pub saturation: f32,
+}
+
+impl WaterSource {
+    pub fn aquifer(top: f32, bottom: f32, recharge_rate: f32, saturation: f32) -> Self {
+        Self {
+            shape: WaterSourceShape::Aquifer { top, bottom },
+            recharge_rate,
+            saturation,
+        }
+    }
+
+    pub fn creek(points: Vec<Vec2>, width: f32, recharge_rate: f32, saturation: f32) -> Self {
+        Self {
+            shape: WaterSourceShape::Creek { points, width },
+            recharge_rate,
+            saturation,
+        }
+    }
+
+    pub fn covers(&self, pos: Vec2) -> bool {
+        match &self.shape {
+            WaterSourceShape::Aquifer { top, bottom } => *top <= pos.y && pos.y < *bottom,
+            WaterSourceShape::Creek { points, width } => points
+                .windows(2)
+                .any(|line| distance_to_segment(pos, line[0], line[1]) <= *width),
+        }
+    }
+
+    /// * returns how much water a cell holding `current` gets this tick.
+    pub fn recharge(&self, current: f32) -> f32 {
+        f32::max(0.0, f32::min(self.recharge_rate, self.saturation - current))
+    }
+}
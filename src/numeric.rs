@@ -1,6 +1,5 @@
 use glam::Vec2;
-use macroquad::rand;
-use num_traits;
+use serde::{Deserialize, Serialize};
 
 pub trait Cap where Self: num_traits::Float {
     fn cap(self, min: Self, max: Self) -> Self;
@@ -16,8 +15,53 @@ impl Cap for f32 {
     }
 }
 
-pub fn rand(till: i32) -> i32 {
-    rand::rand() as i32 % till
+/// A seedable PCG32 generator. All randomness of the model goes through one,
+/// so that the same seed always reproduces the same world.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    const MULTIPLIER: u64 = 6364136223846793005;
+    const INCREMENT: u64 = 1442695040888963407;
+
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Self { state: 0 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(Self::MULTIPLIER).wrapping_add(Self::INCREMENT);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    /// Uniform in `0..till`, or 0 if the range is empty.
+    pub fn rand(&mut self, till: i32) -> i32 {
+        if till <= 0 {
+            return 0;
+        }
+        let till = till as u32;
+        // Reject the incomplete last cycle of `till` to avoid a modulo bias.
+        let zone = u32::MAX - u32::MAX % till;
+        loop {
+            let value = self.next_u32();
+            if value < zone {
+                return (value % till) as i32;
+            }
+        }
+    }
+
+    /// Uniform in `min..max`.
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        let unit = (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32;
+        min + (max - min) * unit
+    }
 }
 
 fn float_min(a: f32, b: f32) -> f32 {
@@ -46,5 +90,68 @@ pub fn distance_to_segment(p: Vec2, w: Vec2, v: Vec2) -> f32 {
     let t = float_max(0.0, float_min(1.0, (p - v).dot(w - v) / l2));
 
     let projection = v + t * (w - v);  // Projection falls on the segment
-    return p.distance(projection);
-}
\ No newline at end of file
+    p.distance(projection)
+}
+
+/// Whether line segment vw passes through the rectangle from `min` to `max`, borders included.
+/// Clips the segment by each pair of sides in turn, see Liang-Barsky.
+pub fn segment_touches_rect(v: Vec2, w: Vec2, min: Vec2, max: Vec2) -> bool {
+    let delta = w - v;
+    // The part of the segment, as v + t * delta, that is still inside.
+    let (mut enter, mut exit) = (0.0f32, 1.0f32);
+    for (start, step, low, high) in [(v.x, delta.x, min.x, max.x), (v.y, delta.y, min.y, max.y)] {
+        if step.abs() < f32::EPSILON {
+            if start < low || start > high {
+                return false;
+            }
+            continue;
+        }
+        let (a, b) = ((low - start) / step, (high - start) / step);
+        enter = float_max(enter, float_min(a, b));
+        exit = float_min(exit, float_max(a, b));
+    }
+    enter <= exit
+}
+
+/// A repeatable value in -1..1 for every point of the integer lattice.
+fn lattice_value(seed: u64, x: i32, y: i32) -> f32 {
+    // SplitMix64 finalizer over the seed and both coordinates.
+    let mut h = seed
+        ^ (x as u32 as u64).wrapping_mul(0x9E3779B97F4A7C15)
+        ^ (y as u32 as u64).wrapping_mul(0xC2B2AE3D27D4EB4F);
+    h = (h ^ (h >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94D049BB133111EB);
+    h ^= h >> 31;
+    (h >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+}
+
+/// Smooth value noise in -1..1, with features about 1 unit apart.
+/// The same seed and position always give the same value.
+pub fn value_noise(seed: u64, pos: Vec2) -> f32 {
+    let corner = pos.floor();
+    let (x, y) = (corner.x as i32, corner.y as i32);
+    let t = pos - corner;
+    // Smoothstep, so that there are no creases along the lattice.
+    let t = t * t * (Vec2::splat(3.0) - 2.0 * t);
+
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let top = lerp(lattice_value(seed, x, y), lattice_value(seed, x + 1, y), t.x);
+    let bottom = lerp(lattice_value(seed, x, y + 1), lattice_value(seed, x + 1, y + 1), t.x);
+    lerp(top, bottom, t.y)
+}
+
+/// `octaves` of value noise, each twice as fine and half as strong as the previous one.
+/// * returns a value in -1..1.
+pub fn fractal_noise(seed: u64, pos: Vec2, octaves: u32) -> f32 {
+    let mut sum = 0.0;
+    let mut total = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+    for octave in 0..octaves {
+        sum += amplitude * value_noise(seed.wrapping_add(octave as u64), pos * frequency);
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    if total > 0.0 { sum / total } else { 0.0 }
+}
@@ -4,8 +4,8 @@ use macroquad::math::Rect;
 use macroquad::prelude::{draw_rectangle, screen_height};
 use macroquad::shapes::draw_poly_lines;
 use macroquad::text::draw_text;
-use crate::model::branch::BranchId;
-use crate::model::map::Map;
+use root_tactics::model::branch::BranchHandle;
+use root_tactics::model::map::Map;
 
 pub struct MainLayout {
     // pub soil_level: f32,
@@ -15,10 +15,10 @@ pub struct MainLayout {
 
 pub struct IngameUi {
     /// Player's plant is always #0, this is the selected one (you can select others too)
-    pub selected: Option<BranchId>,
+    pub selected: Option<(u32, BranchHandle)>,
 
-    /// "Path" to a selected branch - indexes of branches.
-    pub hovered: Option<BranchId>,
+    /// The plant and the branch under the mouse.
+    pub hovered: Option<(u32, BranchHandle)>,
 
     /// 0, 1 or 2 - for different speeds.
     /// On pause, press G to go.
@@ -27,6 +27,8 @@ pub struct IngameUi {
     pub selected_mass: Option<f32>,
     pub selected_nitro_consumption: Option<f32>,
     pub selected_water_consumption: Option<f32>,
+    pub selected_nitro_potential: Option<f32>,
+    pub selected_water_potential: Option<f32>,
 }
 
 impl IngameUi {
@@ -37,7 +39,9 @@ impl IngameUi {
             speed: 0,
             selected_mass: None,
             selected_nitro_consumption: None,
-            selected_water_consumption: None
+            selected_water_consumption: None,
+            selected_nitro_potential: None,
+            selected_water_potential: None,
         }
     }
 }
@@ -70,15 +74,17 @@ pub fn draw_ui(_map: &Map, ui_state: &mut IngameUi, ui_layout: &MainLayout) {
     if ui_state.selected.is_some() {
 
         let draw_line = |text: &str, line_no: f32| {
-            draw_text(text, sidebar_offset, text_top + line_height * line_no, ui_layout.font_size as f32, WHITE);
+            draw_text(text, sidebar_offset, text_top + line_height * line_no, ui_layout.font_size, WHITE);
         };
 
         draw_line("Basic root", 0.0);
         if let Some(mass) = ui_state.selected_mass {
             let descr = format!("Mass: {:.02}", mass);
             draw_line(&descr, 1.0);
-
-            let descr = format!("Brings water/nitro: {:.02}/{:.02}", 0.0, 0.0);
+        }
+        if let Some(water) = ui_state.selected_water_potential {
+            let nitro = ui_state.selected_nitro_potential.unwrap_or_default();
+            let descr = format!("Can suck water/nitro: {:.02}/{:.02}", water, nitro);
             draw_line(&descr, 3.0);
         }
         if let Some(water) = ui_state.selected_water_consumption {